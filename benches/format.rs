@@ -0,0 +1,204 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    ffi::c_void,
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+use jinja_formatter::{new_parser, Formatter};
+
+/// Allocations of Rust and of tree-sitter's C code so far. Unlike timings they don't vary from
+/// run to run, so [`allocations`] reports them alongside the benchmarks.
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn calloc(count: usize, size: usize) -> *mut c_void;
+    fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+unsafe extern "C" fn counting_malloc(size: usize) -> *mut c_void {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    malloc(size)
+}
+
+unsafe extern "C" fn counting_calloc(count: usize, size: usize) -> *mut c_void {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    calloc(count, size)
+}
+
+unsafe extern "C" fn counting_realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    realloc(ptr, size)
+}
+
+const SMALL: &str = include_str!("../tests/fixtures/macros.input.jinja");
+const MEDIUM: &str = include_str!("../tests/fixtures/html.input.jinja");
+
+/// About 100 KiB of the medium template over and over.
+fn large() -> String {
+    MEDIUM.repeat(200)
+}
+
+/// Blocks nested `depth` deep, alternating loops and conditions, each holding an expression.
+fn nested(depth: usize) -> String {
+    let mut template = String::new();
+    for i in 0..depth {
+        if i % 2 == 0 {
+            template.push_str(&format!("{{% for x{i} in items{i} %}}"));
+        } else {
+            template.push_str(&format!("{{% if x{} %}}", i - 1));
+        }
+        template.push_str(&format!("<p>{{{{ x{i} | default('-') }}}}</p>"));
+    }
+    for i in (0..depth).rev() {
+        template.push_str(if i % 2 == 0 {
+            "{% endfor %}"
+        } else {
+            "{% endif %}"
+        });
+    }
+    template
+}
+
+fn templates() -> Vec<(&'static str, String)> {
+    vec![
+        ("small", SMALL.to_string()),
+        ("medium", MEDIUM.to_string()),
+        ("large", large()),
+        ("nested", nested(200)),
+    ]
+}
+
+fn format(c: &mut Criterion) {
+    let formatter = Formatter::default();
+    let mut group = c.benchmark_group("format");
+    for (name, template) in templates() {
+        group.throughput(Throughput::Bytes(template.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            &template,
+            |b, template| b.iter(|| formatter.format(black_box(template)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+/// Parser setup on its own, and formatting with a parser that is set up once, which together
+/// make up [`Formatter::format`].
+fn parser_setup(c: &mut Criterion) {
+    c.bench_function("new_parser", |b| b.iter(new_parser));
+
+    let formatter = Formatter::default();
+    let mut parser = new_parser();
+    let mut group = c.benchmark_group("format_with_parser");
+    for (name, template) in templates() {
+        group.throughput(Throughput::Bytes(template.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            &template,
+            |b, template| {
+                b.iter(|| {
+                    formatter
+                        .format_with_parser(&mut parser, black_box(template))
+                        .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// A keystroke in the middle of the large template, formatted from scratch and with the tree of
+/// the template before it.
+fn edit(c: &mut Criterion) {
+    let formatter = Formatter::default();
+    let template = large();
+    let at = template.len() / 2 + template[template.len() / 2..].find("{{ ").unwrap() + 3;
+    let original = &template[at..at + 1];
+    let mut group = c.benchmark_group("edit");
+    group.throughput(Throughput::Bytes(template.len() as u64));
+    group.bench_function("format", |b| {
+        let mut edited = template.clone();
+        let mut flip = false;
+        b.iter(|| {
+            flip = !flip;
+            edited.replace_range(at..at + 1, if flip { "y" } else { original });
+            formatter.format(black_box(&edited)).unwrap()
+        })
+    });
+    group.bench_function("format_parsed", |b| {
+        let mut parsed = formatter.parse_template(&template).unwrap();
+        let mut flip = false;
+        b.iter(|| {
+            flip = !flip;
+            let text = if flip { "y" } else { original };
+            formatter.reparse(&mut parsed, at..at + 1, text).unwrap();
+            formatter.format_parsed(black_box(&parsed)).unwrap()
+        })
+    });
+    group.finish();
+}
+
+/// Allocations per [`Formatter::format`] with the parser of the thread, and with a new parser
+/// for each call as it was before formatting reused one.
+fn allocations() {
+    let count = |f: &mut dyn FnMut()| {
+        f();
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        f();
+        ALLOCATIONS.load(Ordering::Relaxed) - before
+    };
+    let formatter = Formatter::default();
+    for (name, template) in templates() {
+        let reused = count(&mut || {
+            formatter.format(&template).unwrap();
+        });
+        let fresh = count(&mut || {
+            formatter
+                .format_with_parser(&mut new_parser(), &template)
+                .unwrap();
+        });
+        println!("allocations/{name}: {reused} per format, {fresh} with a new parser");
+    }
+}
+
+criterion_group!(benches, format, parser_setup, edit);
+
+fn main() {
+    // before tree-sitter allocates anything, so that everything it frees went through `malloc`
+    unsafe {
+        tree_sitter::set_allocator(
+            Some(counting_malloc),
+            Some(counting_calloc),
+            Some(counting_realloc),
+            Some(free),
+        );
+    }
+    allocations();
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+}
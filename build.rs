@@ -0,0 +1,21 @@
+use std::{env, fs, path::Path};
+
+/// Exposes the resolved `tree-sitter-jinja2` version as `TREE_SITTER_JINJA2_VERSION`, for the
+/// server's `/version` endpoint.
+fn main() {
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock.display());
+    let lock = fs::read_to_string(lock).unwrap_or_default();
+    let mut lines = lock.lines();
+    let version = lines
+        .by_ref()
+        .position(|line| line == r#"name = "tree-sitter-jinja2""#)
+        .and_then(|_| lines.next())
+        .and_then(|line| line.strip_prefix("version = \""))
+        .and_then(|line| line.strip_suffix('"'))
+        .unwrap_or("unknown");
+    println!("cargo:rustc-env=TREE_SITTER_JINJA2_VERSION={}", version);
+}
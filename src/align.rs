@@ -0,0 +1,49 @@
+//! Lining up the `=` of consecutive `{% set %}` assignments.
+
+use crate::normalize::split_tag;
+
+/// A single-line `{% set target = value %}` rewritten with one space around the `=`, together
+/// with the byte offset right after the target and the column, in characters, of that offset.
+pub(crate) fn set_assignment(tag: &str) -> Option<(String, usize, usize)> {
+    let parts = split_tag(tag)?;
+    if parts.open != "{%" || tag.contains('\n') {
+        return None;
+    }
+    let rest = parts.inner.trim().strip_prefix("set")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let (target, value) = rest.split_once('=')?;
+    let (target, value) = (target.trim(), value.trim());
+    let simple_target = target
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | ',' | ' '));
+    if target.is_empty() || !simple_target || value.is_empty() || value.starts_with('=') {
+        return None;
+    }
+
+    let mut assignment = parts.open.to_string();
+    assignment.extend(parts.open_marker);
+    assignment.push_str(" set ");
+    assignment.push_str(target);
+    let target_end = assignment.len();
+    assignment.push_str(" = ");
+    assignment.push_str(value);
+    assignment.push(' ');
+    assignment.extend(parts.close_marker);
+    assignment.push_str(parts.close);
+    let column = assignment[..target_end].chars().count();
+    Some((assignment, target_end, column))
+}
+
+/// Pads the targets of a run of assignments written to `text` at the same indent, given as
+/// the offsets after their targets and the columns of those, so that their `=` line up.
+/// Empties `group`.
+pub(crate) fn align_assignments(text: &mut String, group: &mut Vec<(usize, usize)>) {
+    let column = group.iter().map(|&(_, column)| column).max().unwrap_or(0);
+    // later offsets first, so that the earlier ones stay valid
+    for &(offset, target_column) in group.iter().rev() {
+        text.insert_str(offset, &" ".repeat(column - target_column));
+    }
+    group.clear();
+}
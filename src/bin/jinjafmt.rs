@@ -0,0 +1,279 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::Parser;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use jinja_formatter::{
+    config::{find_config, load_config},
+    format_string_report, unified_diff, FormatOptions, IndentStyle,
+};
+use rayon::prelude::*;
+
+const DEFAULT_GLOBS: &[&str] = &["**/*.jinja", "**/*.jinja2", "**/*.j2"];
+
+/// Format Jinja templates in place.
+#[derive(Parser)]
+#[command(name = "jinjafmt", version)]
+struct Args {
+    /// Template files or directories to format in place
+    #[arg(required_unless_present = "stdin")]
+    files: Vec<PathBuf>,
+    /// Which files to pick up when walking a directory, e.g. `**/*.html.j2`. Can be repeated
+    #[arg(long = "glob", value_name = "PATTERN")]
+    globs: Vec<String>,
+    /// Also walk into paths excluded by `.gitignore`, `.ignore` and the global gitignore
+    #[arg(long)]
+    no_ignore: bool,
+    /// Read a template from stdin and write the result to stdout
+    #[arg(long, conflicts_with = "files")]
+    stdin: bool,
+    /// Don't write anything, exit with 1 if any template needs formatting
+    #[arg(long)]
+    check: bool,
+    /// Don't write anything, print a unified diff of the changes and exit with 1 if there are any
+    #[arg(long, conflicts_with = "check")]
+    diff: bool,
+    /// Config file to use instead of the nearest `.jinjafmt.toml`
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Spaces per indent level
+    #[arg(long)]
+    indent_size: Option<usize>,
+    /// `spaces` or `tabs`
+    #[arg(long, value_parser = parse_indent_style)]
+    indent_style: Option<IndentStyle>,
+}
+
+fn parse_indent_style(value: &str) -> Result<IndentStyle, String> {
+    match value {
+        "spaces" => Ok(IndentStyle::Spaces),
+        "tabs" => Ok(IndentStyle::Tabs),
+        _ => Err(format!("expected `spaces` or `tabs`, got `{}`", value)),
+    }
+}
+
+/// What to do with a template that needs formatting.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Write,
+    Check,
+    Diff,
+}
+
+impl Args {
+    fn mode(&self) -> Mode {
+        if self.check {
+            Mode::Check
+        } else if self.diff {
+            Mode::Diff
+        } else {
+            Mode::Write
+        }
+    }
+
+    /// Options from the config file, overridden by command line flags.
+    fn options(&self) -> Result<FormatOptions, String> {
+        let config = match &self.config {
+            Some(path) => Some(path.clone()),
+            None => std::env::current_dir()
+                .ok()
+                .and_then(|dir| find_config(&dir)),
+        };
+        let mut options = match config {
+            Some(path) => load_config(&path).map_err(|err| err.to_string())?,
+            None => FormatOptions::default(),
+        };
+        if let Some(indent_size) = self.indent_size {
+            options.indent_size = indent_size;
+        }
+        if let Some(indent_style) = self.indent_style {
+            options.indent_style = indent_style;
+        }
+        Ok(options)
+    }
+}
+
+fn format_stdin(options: &FormatOptions, mode: Mode) -> ExitCode {
+    let mut input = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut input) {
+        eprintln!("error: failed to read stdin: {}", err);
+        return ExitCode::from(2);
+    }
+    let result = format_string_report(&input, options).map(|report| {
+        for warning in &report.warnings {
+            eprintln!("warning: <stdin>: {}", warning);
+        }
+        report.output
+    });
+    match result {
+        Ok(formatted) if mode != Mode::Write => {
+            if formatted == input {
+                return ExitCode::SUCCESS;
+            }
+            if mode == Mode::Diff {
+                print!("{}", unified_diff("<stdin>", &input, &formatted));
+            } else {
+                println!("<stdin> is not formatted");
+            }
+            ExitCode::from(1)
+        }
+        Ok(formatted) => {
+            if let Err(err) = io::stdout().write_all(formatted.as_bytes()) {
+                eprintln!("error: failed to write stdout: {}", err);
+                return ExitCode::from(2);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: <stdin>: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Returns `None` if the file is already formatted. Otherwise the file is written back in
+/// `Mode::Write`, and the result holds its diff in `Mode::Diff` (empty in the other modes).
+fn format_file(options: &FormatOptions, path: &Path, mode: Mode) -> Result<Option<String>, String> {
+    let input = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let report = format_string_report(&input, options).map_err(|err| err.to_string())?;
+    for warning in &report.warnings {
+        eprintln!("warning: {}: {}", path.display(), warning);
+    }
+    let formatted = report.output;
+    if formatted == input {
+        return Ok(None);
+    }
+    match mode {
+        Mode::Write => fs::write(path, formatted).map_err(|err| err.to_string())?,
+        Mode::Check => {}
+        Mode::Diff => {
+            let name = path.display().to_string();
+            return Ok(Some(unified_diff(&name, &input, &formatted)));
+        }
+    }
+    Ok(Some(String::new()))
+}
+
+fn build_globs(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    if patterns.is_empty() {
+        for pattern in DEFAULT_GLOBS {
+            builder.add(Glob::new(pattern)?);
+        }
+    }
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+/// Expands directories into the templates below them matching `globs`, leaving out ignored
+/// paths unless `no_ignore` is set. Explicitly named files are kept as they are.
+fn collect_files(paths: &[PathBuf], globs: &GlobSet, no_ignore: bool) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if !path.is_dir() {
+            files.push(path.clone());
+            continue;
+        }
+        let walk = WalkBuilder::new(path)
+            .git_ignore(!no_ignore)
+            .git_global(!no_ignore)
+            .git_exclude(!no_ignore)
+            .ignore(!no_ignore)
+            .parents(!no_ignore)
+            // a `.gitignore` applies outside of a git checkout too
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walk {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    eprintln!("warning: {}", err);
+                    continue;
+                }
+            };
+            let relative = entry.path().strip_prefix(path).unwrap_or(entry.path());
+            let is_file = entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file());
+            if is_file && globs.is_match(relative) {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    files
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let options = match args.options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mode = args.mode();
+    if args.stdin {
+        return format_stdin(&options, mode);
+    }
+
+    let globs = match build_globs(&args.globs) {
+        Ok(globs) => globs,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+    let files = collect_files(&args.files, &globs, args.no_ignore);
+    let results: Vec<_> = files
+        .par_iter()
+        .map(|path| format_file(&options, path, mode))
+        .collect();
+
+    let (mut changed, mut unchanged, mut failed) = (0, 0, 0);
+    for (path, result) in files.iter().zip(results) {
+        match result {
+            Ok(Some(diff)) => {
+                match mode {
+                    Mode::Write => println!("Formatted {}", path.display()),
+                    Mode::Check => println!("{} is not formatted", path.display()),
+                    Mode::Diff => print!("{}", diff),
+                }
+                changed += 1;
+            }
+            Ok(None) => unchanged += 1,
+            Err(err) => {
+                eprintln!("error: {}: {}", path.display(), err);
+                failed += 1;
+            }
+        }
+    }
+    if args.files.iter().any(|path| path.is_dir()) {
+        let changed_label = if mode == Mode::Write {
+            "formatted"
+        } else {
+            "unformatted"
+        };
+        println!(
+            "{} {}, {} unchanged, {} failed",
+            changed, changed_label, unchanged, failed
+        );
+    }
+
+    if failed > 0 {
+        ExitCode::from(2)
+    } else if mode != Mode::Write && changed > 0 {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,39 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::FormatOptions;
+
+pub const CONFIG_FILE_NAME: &str = ".jinjafmt.toml";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Toml(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ConfigError::Toml(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Looks for `.jinjafmt.toml` in `start` and each of its ancestors, nearest first.
+pub fn find_config(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Reads a config file. Keys that are left out keep their default value.
+pub fn load_config(path: &Path) -> Result<FormatOptions, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+    toml::from_str(&text).map_err(|err| ConfigError::Toml(path.to_path_buf(), err))
+}
@@ -0,0 +1,93 @@
+use crate::formatter::stmt_keyword_node;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SyntaxError {
+    pub message: String,
+    pub start_byte: usize,
+    pub end_byte: usize,
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, counted in bytes.
+    pub column: usize,
+    pub snippet: String,
+}
+
+impl SyntaxError {
+    fn new(node: tree_sitter::Node, source: &[u8]) -> Self {
+        let message = if node.is_missing() {
+            format!("missing `{}`", node.kind())
+        } else {
+            "unexpected input".to_string()
+        };
+        let position = node.start_position();
+        SyntaxError {
+            message,
+            start_byte: node.start_byte(),
+            end_byte: node.end_byte(),
+            line: position.row + 1,
+            column: position.column + 1,
+            snippet: String::from_utf8_lossy(&source[node.byte_range()]).into_owned(),
+        }
+    }
+}
+
+/// Collects the outermost ERROR and MISSING nodes of the tree, in source order.
+pub fn collect_syntax_errors(root_node: tree_sitter::Node, source: &[u8]) -> Vec<SyntaxError> {
+    let mut errors = Vec::new();
+    collect(root_node, source, &mut errors);
+    errors
+}
+
+fn collect(node: tree_sitter::Node, source: &[u8], errors: &mut Vec<SyntaxError>) {
+    if node.is_error() || node.is_missing() {
+        errors.push(SyntaxError::new(node, source));
+        return;
+    }
+    if !node.has_error() {
+        return;
+    }
+    for i in 0..node.child_count() {
+        let child = node.child(i).unwrap();
+        if i == 1 && is_extension_tag(node) {
+            continue;
+        }
+        collect(child, source, errors);
+    }
+}
+
+/// Tags the grammar doesn't know, like `{% cache %}`, parse with a missing keyword in front of
+/// the tag name. Those are valid Jinja extensions rather than syntax errors.
+fn is_extension_tag(node: tree_sitter::Node) -> bool {
+    node.kind() == "statement"
+        && stmt_keyword_node(node).is_some_and(|name| name.kind() == "identifier")
+}
+
+/// Warnings about an `{% extends %}` that isn't the first tag of the template. Jinja renders
+/// whatever comes before it, so it belongs on the first line, after comments at most.
+pub(crate) fn extends_warnings(root_node: tree_sitter::Node, source: &[u8]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut text_start = 0;
+    let mut first = true;
+    for i in 0..root_node.child_count() {
+        let node = root_node.child(i).unwrap();
+        let text_before = &source[text_start..node.start_byte()];
+        text_start = node.end_byte();
+        if !text_before.iter().all(u8::is_ascii_whitespace) {
+            first = false;
+        }
+        if node.kind() == "comment" {
+            continue;
+        }
+        let is_extends = node.kind() == "statement"
+            && stmt_keyword_node(node)
+                .is_some_and(|keyword| &source[keyword.byte_range()] == b"extends");
+        if is_extends && !first {
+            warnings.push(format!(
+                "`extends` on line {} is not the first tag of the template",
+                node.start_position().row + 1
+            ));
+        }
+        first = false;
+    }
+    warnings
+}
@@ -0,0 +1,66 @@
+use std::ops::RangeInclusive;
+
+use similar::{capture_diff_slices, Algorithm, DiffTag, TextDiff};
+
+/// Line-based unified diff from `input` to `formatted`, with `name` in the file headers.
+/// Empty when the two are equal.
+pub fn unified_diff(name: &str, input: &str, formatted: &str) -> String {
+    if input == formatted {
+        return String::new();
+    }
+    TextDiff::from_lines(input, formatted)
+        .unified_diff()
+        .header(name, &format!("{} (formatted)", name))
+        .to_string()
+}
+
+/// How many lines differ between `input` and `formatted`: each changed line counts once, as do
+/// lines only one side has.
+#[cfg(feature = "server")]
+pub(crate) fn changed_lines(input: &str, formatted: &str) -> usize {
+    if input == formatted {
+        return 0;
+    }
+    TextDiff::from_lines(input, formatted)
+        .ops()
+        .iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| op.old_range().len().max(op.new_range().len()))
+        .sum()
+}
+
+/// Takes the lines of `formatted` that replace lines `lines` (1-based, inclusive) of `input`
+/// and keeps every other line of `input` as it is. Lines are matched up ignoring whitespace, so
+/// a reindented line is replaced on its own rather than with the whole hunk around it.
+pub(crate) fn splice_lines(input: &str, formatted: &str, lines: RangeInclusive<usize>) -> String {
+    let old: Vec<&str> = input.split_inclusive('\n').collect();
+    let new: Vec<&str> = formatted.split_inclusive('\n').collect();
+    let key = |line: &&str| line.split_whitespace().collect::<String>();
+    let old_keys: Vec<String> = old.iter().map(key).collect();
+    let new_keys: Vec<String> = new.iter().map(key).collect();
+    // 0-based, half open
+    let (start, end) = (lines.start().saturating_sub(1), *lines.end());
+    let selected = |line: usize| start <= line && line < end;
+
+    let mut spliced = String::new();
+    for op in capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys) {
+        let (old_range, new_range) = (op.old_range(), op.new_range());
+        match op.tag() {
+            DiffTag::Equal => {
+                for (i, j) in old_range.zip(new_range) {
+                    spliced.push_str(if selected(i) { new[j] } else { old[i] });
+                }
+            }
+            DiffTag::Insert if start <= old_range.start && old_range.start <= end => {
+                spliced.extend(new[new_range].iter().copied());
+            }
+            DiffTag::Delete | DiffTag::Replace
+                if old_range.start < end && old_range.end > start =>
+            {
+                spliced.extend(new[new_range].iter().copied());
+            }
+            _ => spliced.extend(old[old_range].iter().copied()),
+        }
+    }
+    spliced
+}
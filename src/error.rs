@@ -0,0 +1,71 @@
+use std::fmt;
+
+use crate::SyntaxError;
+
+/// Why a template couldn't be formatted. The `Display` form is a one-line message fit for users.
+/// Empty and whitespace-only templates aren't an error, they format to an empty output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The template has syntax errors. Empty if the parser gave up without a tree.
+    ParseError(Vec<SyntaxError>),
+    /// A block tag the formatter has no rule for.
+    UnknownKeyword(String),
+    /// A block tag doesn't match the enclosing block. `expected` is the end tag that would
+    /// have been valid, `found` is `None` when the template ends with blocks still open.
+    UnbalancedBlock {
+        expected: Option<String>,
+        found: Option<String>,
+        /// 1-based position of the `found` tag, or of the unclosed block's opening tag.
+        line: usize,
+        /// 1-based, counted in bytes.
+        column: usize,
+    },
+    /// The source of [`crate::Formatter::format_jinja_node`], or a file of an archive posted to
+    /// the server, isn't valid UTF-8.
+    Utf8,
+    /// Parsing took longer than `FormatOptions::parse_timeout_micros`.
+    Timeout,
+    /// Writing the output failed, with [`crate::Formatter::format_to_writer`].
+    Io(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::ParseError(errors) => match errors.first() {
+                Some(first) => write!(
+                    f,
+                    "Failed to parse code: {} at line {}, column {}",
+                    first.message, first.line, first.column
+                ),
+                None => write!(f, "Failed to parse code"),
+            },
+            FormatError::UnknownKeyword(keyword) => write!(f, "Unknown keyword: {}", keyword),
+            FormatError::UnbalancedBlock {
+                expected,
+                found,
+                line,
+                column,
+            } => {
+                match (expected, found) {
+                    (Some(expected), Some(found)) => write!(
+                        f,
+                        "Unbalanced block: expected `{}` but found `{}`",
+                        expected, found
+                    ),
+                    (None, Some(found)) => write!(f, "Unbalanced block: unexpected `{}`", found),
+                    (Some(expected), None) => {
+                        write!(f, "Unbalanced block: missing `{}`", expected)
+                    }
+                    (None, None) => write!(f, "Unbalanced block"),
+                }?;
+                write!(f, " at line {}, column {}", line, column)
+            }
+            FormatError::Utf8 => write!(f, "Template is not valid UTF-8"),
+            FormatError::Timeout => write!(f, "Parsing the template timed out"),
+            FormatError::Io(err) => write!(f, "Failed to write the output: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
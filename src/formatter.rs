@@ -0,0 +1,1624 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::ops::{Range, RangeInclusive};
+
+use crate::align::{align_assignments, set_assignment};
+use crate::diagnostics::extends_warnings;
+use crate::diff::splice_lines;
+use crate::html::Html;
+use crate::incremental::{input_edit, ParsedTemplate};
+use crate::normalize::{
+    normalize_operator_spacing, normalize_quotes, normalize_tag_spacing, trim_line_ends,
+    trim_tag_line_ends, Padding,
+};
+use crate::wrap::{expand_literal, wrap_tag, KEYWORDS};
+use crate::{
+    collect_syntax_errors, FormatError, FormatOptions, IndentStyle, LineEnding, PipeSpacing,
+    QuoteStyle, SyntaxError, TrailingComma, TrailingNewline,
+};
+
+/// Runs the indentation of most levels is sliced from, see [`Formatter::indent`].
+const SPACES: &str = match std::str::from_utf8(&[b' '; 512]) {
+    Ok(spaces) => spaces,
+    Err(_) => unreachable!(),
+};
+const TABS: &str = match std::str::from_utf8(&[b'\t'; 64]) {
+    Ok(tabs) => tabs,
+    Err(_) => unreachable!(),
+};
+
+pub fn peek_jinja_stmt_keyword(node: tree_sitter::Node, source: &[u8]) -> Option<String> {
+    let kind = node.kind();
+    if kind != "statement" {
+        return None;
+    }
+    if let Some(keyword_node) = stmt_keyword_node(node) {
+        if let Ok(text) = keyword_node.utf8_text(source) {
+            return match text {
+                // Jinja2 built-in tags
+                "if" | "elif" | "else" | "endif" | "for" | "endfor" | "macro" | "endmacro"
+                | "call" | "endcall" | "filter" | "endfilter" | "block" | "endblock" | "endset"
+                | "with" | "endwith" | "autoescape" | "endautoescape" | "raw" | "endraw" => {
+                    return Some(text.to_string());
+                }
+                // only `{% set x %}...{% endset %}` opens a block, `{% set x = 1 %}` doesn't
+                "set" if !has_assignment(node, source) => Some(text.to_string()),
+                // jinja2.ext.i18n
+                "trans" | "pluralize" | "endtrans" => Some(text.to_string()),
+                // Twig's replacement for its own `filter` tag. Jinja2 has no `apply`, so it is
+                // kept apart from `filter` rather than treated as an alias.
+                "apply" | "endapply" => Some(text.to_string()),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+/// The grammar only has `keyword` nodes for a fixed set of tag names. Any other tag, such as
+/// `{% apply %}`, parses as an empty keyword followed by an identifier holding the name.
+pub(crate) fn stmt_keyword_node(node: tree_sitter::Node) -> Option<tree_sitter::Node> {
+    let keyword = node.child(1)?;
+    if keyword.kind() == "keyword" && keyword.byte_range().is_empty() {
+        return node.child(2).filter(|child| child.kind() == "identifier");
+    }
+    Some(keyword)
+}
+
+fn has_assignment(node: tree_sitter::Node, source: &[u8]) -> bool {
+    let mut depth = 0usize;
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        if child.kind() != "operator" {
+            continue;
+        }
+        let Ok(text) = child.utf8_text(source) else {
+            continue;
+        };
+        let bytes = text.as_bytes();
+        for (i, &b) in bytes.iter().enumerate() {
+            match b {
+                b'(' | b'[' => depth += 1,
+                b')' | b']' => depth = depth.saturating_sub(1),
+                b'=' if depth == 0 => {
+                    let prev = i.checked_sub(1).map(|j| bytes[j]);
+                    let next = bytes.get(i + 1).copied();
+                    if !matches!(prev, Some(b'=' | b'!' | b'<' | b'>')) && next != Some(b'=') {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+/// Finds the next `{% name %}` tag at or after `from`, allowing whitespace-control markers.
+/// Returns its byte range.
+fn find_tag(source: &str, from: usize, name: &str) -> Option<(usize, usize)> {
+    let bytes = source.as_bytes();
+    let mut search = from;
+    while let Some(offset) = source.get(search..)?.find("{%") {
+        let start = search + offset;
+        search = start + 2;
+
+        let mut pos = start + 2;
+        if matches!(bytes.get(pos), Some(b'-' | b'+')) {
+            pos += 1;
+        }
+        while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if !source[pos..].starts_with(name) {
+            continue;
+        }
+        pos += name.len();
+        while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if matches!(bytes.get(pos), Some(b'-' | b'+')) {
+            pos += 1;
+        }
+        if source[pos..].starts_with("%}") {
+            return Some((start, pos + 2));
+        }
+    }
+    None
+}
+
+fn mask_raw_blocks(input: &str) -> String {
+    let mut masked = String::with_capacity(input.len());
+    let mut pos = 0;
+    while let Some((_, open_end)) = find_tag(input, pos, "raw") {
+        let Some((close_start, _)) = find_tag(input, open_end, "endraw") else {
+            break;
+        };
+        masked.push_str(&input[pos..open_end]);
+        for c in input[open_end..close_start].chars() {
+            match c {
+                '\n' => masked.push('\n'),
+                _ => masked.push_str(&" ".repeat(c.len_utf8())),
+            }
+        }
+        pos = close_start;
+    }
+    masked.push_str(&input[pos..]);
+    masked
+}
+
+/// Returns `on` or `off` for `{# fmt: on #}` and `{# fmt: off #}` comments.
+fn fmt_directive<'a>(node: tree_sitter::Node, source: &'a [u8]) -> Option<&'a str> {
+    if node.kind() != "comment" {
+        return None;
+    }
+    let text = node.utf8_text(source).ok()?;
+    let inner = text.strip_prefix("{#")?.strip_suffix("#}")?;
+    let inner = inner.trim_matches(|c: char| c == '-' || c.is_whitespace());
+    let directive = inner.strip_prefix("fmt:")?.trim();
+    matches!(directive, "on" | "off").then_some(directive)
+}
+
+/// Whether most lines of `input` end with `\r\n` rather than a bare `\n`.
+fn is_mostly_crlf(input: &str) -> bool {
+    let lines = input.matches('\n').count();
+    let crlf_lines = input.matches("\r\n").count();
+    crlf_lines > lines - crlf_lines
+}
+
+/// Offsets of the filter pipes in `node`, relative to its start. The grammar folds a `|` into
+/// `operator` nodes, possibly together with brackets and whitespace, but never into strings.
+fn filter_pipes(node: tree_sitter::Node, source: &[u8]) -> Vec<usize> {
+    let mut pipes = Vec::new();
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.kind() != "operator" {
+            continue;
+        }
+        let bytes = &source[child.byte_range()];
+        let offset = child.start_byte() - node.start_byte();
+        pipes.extend(
+            (0..bytes.len())
+                .filter(|&i| bytes[i] == b'|')
+                .map(|i| offset + i),
+        );
+    }
+    pipes
+}
+
+const BINARY_OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "//", "**", "<", ">", "+", "-", "*", "/",
+];
+
+/// The `operator` nodes below `node`, including the ones of dict literals, in source order.
+fn operator_nodes<'tree>(
+    node: tree_sitter::Node<'tree>,
+    operators: &mut Vec<tree_sitter::Node<'tree>>,
+) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        match child.kind() {
+            "operator" => operators.push(child),
+            "object" => operator_nodes(child, operators),
+            _ => {}
+        }
+    }
+}
+
+/// The `do` of a `{% do %}` tag, relative to the start of `node`. Its expression gets the single
+/// space after `do` that an expression tag gets after `{{`.
+fn do_keyword(node: tree_sitter::Node, source: &[u8]) -> Option<Range<usize>> {
+    let keyword = stmt_keyword_node(node).filter(|keyword| keyword.kind() == "keyword")?;
+    if &source[keyword.byte_range()] != b"do" {
+        return None;
+    }
+    let offset = keyword.start_byte() - node.start_byte();
+    Some(offset..offset + 2)
+}
+
+/// The keyword, the parentheses of the parameter list and the `=` of the parameter defaults of a
+/// `{% macro %}` tag, or of the caller parameters and the arguments of the called macro of a
+/// `{% call %}` tag, relative to the start of `node`. The parentheses hug the name and the
+/// parameters: `{% macro m(a, b=1) %}` and `{% call(user) render(users, wide=true) %}`.
+fn signature(
+    node: tree_sitter::Node,
+    source: &[u8],
+    default_padding: Padding,
+) -> Vec<(Range<usize>, Padding)> {
+    let mut signature = Vec::new();
+    let Some(keyword) = stmt_keyword_node(node) else {
+        return signature;
+    };
+    let keyword_offset = keyword.start_byte() - node.start_byte();
+    let keyword_range = keyword_offset..keyword_offset + keyword.byte_range().len();
+    let is_macro = &source[keyword.byte_range()] == b"macro";
+    let params = match is_macro {
+        true => keyword.next_sibling().and_then(|name| name.next_sibling()),
+        false => keyword.next_sibling(),
+    };
+    let opens_list = |node: &tree_sitter::Node| {
+        node.kind() == "operator" && source[node.byte_range()][0] == b'('
+    };
+    let params = params.filter(opens_list);
+    // `call(user)` hugs its caller parameters, only a bare `call` is followed by a space
+    if is_macro || params.is_none() {
+        signature.push((keyword_range, Padding::Spaced));
+    }
+    let after_params = match params {
+        Some(params) => {
+            let after = parameter_list(node, params, source, default_padding, &mut signature);
+            if is_macro {
+                return signature;
+            }
+            after
+        }
+        None => keyword.next_sibling(),
+    };
+
+    // the called macro, as in `{% call ns.render(users) %}`
+    let mut next = after_params;
+    while let Some(child) = next {
+        if opens_list(&child) {
+            parameter_list(node, child, source, default_padding, &mut signature);
+            break;
+        }
+        if child.kind() != "identifier" && &source[child.byte_range()] != b"." {
+            break;
+        }
+        next = child.next_sibling();
+    }
+    signature
+}
+
+/// Adds the parentheses and the `=` of the parenthesized list starting at the operator `open`
+/// to `signature`, and returns the node after the one closing it.
+fn parameter_list<'tree>(
+    node: tree_sitter::Node,
+    open: tree_sitter::Node<'tree>,
+    source: &[u8],
+    default_padding: Padding,
+    signature: &mut Vec<(Range<usize>, Padding)>,
+) -> Option<tree_sitter::Node<'tree>> {
+    let mut depth = 0usize;
+    let mut next = Some(open);
+    while let Some(child) = next {
+        next = child.next_sibling();
+        if child.kind() != "operator" {
+            continue;
+        }
+        let bytes = &source[child.byte_range()];
+        let offset = child.start_byte() - node.start_byte();
+        for (i, &byte) in bytes.iter().enumerate() {
+            let range = offset + i..offset + i + 1;
+            match byte {
+                b'(' | b'[' | b'{' => {
+                    if depth == 0 {
+                        signature.push((range, Padding::Tight));
+                    }
+                    depth += 1;
+                }
+                b')' | b']' | b'}' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        signature.push((range, Padding::Trailing));
+                        return next;
+                    }
+                }
+                // not part of `==`, `!=`, `<=` or `>=`
+                b'=' if depth == 1
+                    && !(i > 0 && b"=!<>".contains(&bytes[i - 1]))
+                    && bytes.get(i + 1) != Some(&b'=') =>
+                {
+                    signature.push((range, default_padding));
+                }
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Offsets, relative to the start of `node`, of the commas between its arguments and items.
+/// Commas in string literals aren't `operator` nodes, so they're never picked up.
+fn commas(node: tree_sitter::Node, source: &[u8]) -> Vec<usize> {
+    let mut operators = Vec::new();
+    operator_nodes(node, &mut operators);
+    let mut commas = Vec::new();
+    for operator in operators {
+        let offset = operator.start_byte() - node.start_byte();
+        let bytes = &source[operator.byte_range()];
+        commas.extend(
+            (0..bytes.len())
+                .filter(|&i| bytes[i] == b',')
+                .map(|i| offset + i),
+        );
+    }
+    commas
+}
+
+/// Offsets, relative to the start of `node`, of the brackets and commas of its operators and
+/// dict literals, with the bracket or comma.
+fn punctuation(
+    node: tree_sitter::Node,
+    source: &[u8],
+    offset: usize,
+    marks: &mut Vec<(usize, u8)>,
+) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        match child.kind() {
+            "operator" | "{" | "}" => {
+                let start = child.start_byte() - offset;
+                marks.extend(
+                    source[child.byte_range()]
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, byte)| b"()[]{},".contains(byte))
+                        .map(|(i, &byte)| (start + i, byte)),
+                );
+            }
+            "object" => punctuation(child, source, offset, marks),
+            _ => {}
+        }
+    }
+}
+
+/// Whether the bracket at `open` of `text` starts a literal rather than a call or a subscript,
+/// going by what it follows.
+fn opens_literal(text: &[u8], open: usize) -> bool {
+    if text[open] == b'{' {
+        return true;
+    }
+    let before = text[..open].trim_ascii_end();
+    match before.last() {
+        None => true,
+        Some(&byte) if byte.is_ascii_alphanumeric() || byte == b'_' => {
+            let start = before
+                .iter()
+                .rposition(|byte| !byte.is_ascii_alphanumeric() && *byte != b'_')
+                .map_or(0, |i| i + 1);
+            KEYWORDS
+                .iter()
+                .any(|keyword| keyword.as_bytes() == &before[start..])
+        }
+        Some(&byte) => !b")]}\"'".contains(&byte),
+    }
+}
+
+/// A dict, list or tuple literal found by [`inline_literals`], with offsets relative to the
+/// start of the node.
+struct InlineLiteral {
+    close: usize,
+    trailing_comma: Option<usize>,
+}
+
+/// The dict, list and tuple literals of `node` that fit on one line and hold items, except
+/// single-item tuples like `(a,)` whose comma makes them tuples. Strings aren't part of the
+/// operators, so brackets and commas in them are never found.
+fn inline_literals(node: tree_sitter::Node, source: &[u8]) -> Vec<InlineLiteral> {
+    let text = &source[node.byte_range()];
+    let mut marks = Vec::new();
+    punctuation(node, source, node.start_byte(), &mut marks);
+    // open bracket, whether it starts a literal, its commas and the last one
+    let mut open: Vec<(usize, bool, usize, Option<usize>)> = Vec::new();
+    let mut literals = Vec::new();
+    for (offset, mark) in marks {
+        match mark {
+            b'(' | b'[' | b'{' => open.push((offset, opens_literal(text, offset), 0, None)),
+            b',' => {
+                if let Some((_, _, commas, last)) = open.last_mut() {
+                    *commas += 1;
+                    *last = Some(offset);
+                }
+            }
+            _ => {
+                let Some((start, literal, commas, last)) = open.pop() else {
+                    continue;
+                };
+                if !literal || text[start..offset].contains(&b'\n') {
+                    continue;
+                }
+                let trailing_comma =
+                    last.filter(|&last| text[last + 1..offset].trim_ascii().is_empty());
+                let empty = text[start + 1..offset].trim_ascii().is_empty();
+                let items = commas + 1 - usize::from(trailing_comma.is_some());
+                // `(a)` is no tuple at all
+                let single_tuple = text[start] == b'(' && items == 1;
+                if empty || single_tuple {
+                    continue;
+                }
+                literals.push(InlineLiteral {
+                    close: offset,
+                    trailing_comma,
+                });
+            }
+        }
+    }
+    literals
+}
+
+/// Byte ranges, relative to the start of `node`, of its binary operators, `and` and `or`. The
+/// grammar folds operators into `operator` nodes along with brackets, commas and signs.
+fn binary_operators(node: tree_sitter::Node, source: &[u8]) -> Vec<Range<usize>> {
+    let text = &source[node.byte_range()];
+    let mut operators = Vec::new();
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        let bytes = &source[child.byte_range()];
+        let offset = child.start_byte() - node.start_byte();
+        match child.kind() {
+            "keyword" | "identifier" if bytes == b"and" || bytes == b"or" => {
+                operators.push(offset..offset + bytes.len());
+            }
+            "operator" => {
+                let mut i = 0;
+                while i < bytes.len() {
+                    let Some(operator) = BINARY_OPERATORS
+                        .iter()
+                        .find(|operator| bytes[i..].starts_with(operator.as_bytes()))
+                    else {
+                        i += 1;
+                        continue;
+                    };
+                    let range = offset + i..offset + i + operator.len();
+                    // the grammar also takes whitespace-control markers for operators
+                    let closes_tag = text[range.end..].starts_with(b"}}")
+                        || text[range.end..].starts_with(b"%}");
+                    if !closes_tag && follows_operand(&text[..range.start]) {
+                        operators.push(range);
+                    }
+                    i += operator.len();
+                }
+            }
+            _ => {}
+        }
+    }
+    operators
+}
+
+/// Whether an operator after `before` is binary rather than a sign, i.e. it follows a name, a
+/// literal or a closing bracket. The `-` of `1e-5` is part of the number.
+fn follows_operand(before: &[u8]) -> bool {
+    let before = before.trim_ascii_end();
+    let word_start = before
+        .iter()
+        .rposition(|c| !(c.is_ascii_alphanumeric() || *c == b'_' || *c == b'.'))
+        .map_or(0, |i| i + 1);
+    let word = &before[word_start..];
+    let exponent = word.first().is_some_and(u8::is_ascii_digit)
+        && word.last().is_some_and(|c| *c == b'e' || *c == b'E');
+    match before.last() {
+        Some(b')' | b']' | b'}' | b'"' | b'\'') => true,
+        _ if word.is_empty() || exponent => false,
+        _ => !matches!(
+            word,
+            b"if" | b"elif" | b"else" | b"and" | b"or" | b"not" | b"in" | b"is" | b"do"
+        ),
+    }
+}
+
+/// Whether `keyword` starts another branch of the block it is in.
+fn is_branch(keyword: &str) -> bool {
+    matches!(keyword, "elif" | "else" | "pluralize")
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, FormatError> {
+    std::str::from_utf8(bytes).map_err(|_| FormatError::Utf8)
+}
+
+/// Room for the output of formatting `len` bytes, which mostly grows by the indentation added.
+fn output_capacity(len: usize) -> usize {
+    len + len / 8
+}
+
+pub fn new_parser() -> tree_sitter::Parser {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(&tree_sitter_jinja2::language())
+        .expect("Error loading jinja2 grammar");
+    parser
+}
+
+thread_local! {
+    /// The parser of the formatting calls on this thread that don't bring their own, so that
+    /// the grammar is loaded and the parser's buffers are allocated only once per thread.
+    static PARSER: RefCell<tree_sitter::Parser> = RefCell::new(new_parser());
+}
+
+/// Runs `f` with this thread's parser, or with a new one if it is already in use.
+fn with_parser<T>(f: impl FnOnce(&mut tree_sitter::Parser) -> T) -> T {
+    PARSER.with(|parser| match parser.try_borrow_mut() {
+        Ok(mut parser) => f(&mut parser),
+        Err(_) => f(&mut new_parser()),
+    })
+}
+
+/// Parses `input`, failing on syntax errors unless `best_effort` is set.
+fn parse(
+    parser: &mut tree_sitter::Parser,
+    input: &str,
+    timeout_micros: u64,
+    best_effort: bool,
+) -> Result<tree_sitter::Tree, FormatError> {
+    // raw bodies are blanked out so the grammar never sees the tags inside them
+    let tree = parse_masked(parser, &mask_raw_blocks(input), None, timeout_micros)?;
+    if !best_effort {
+        check_syntax(&tree, input)?;
+    }
+    Ok(tree)
+}
+
+/// Parses `masked`, an input with its raw bodies blanked out, reusing what is left of
+/// `old_tree` after editing it to match.
+fn parse_masked(
+    parser: &mut tree_sitter::Parser,
+    masked: &str,
+    old_tree: Option<&tree_sitter::Tree>,
+    timeout_micros: u64,
+) -> Result<tree_sitter::Tree, FormatError> {
+    parser.set_timeout_micros(timeout_micros);
+    let Some(tree) = parser.parse(masked, old_tree) else {
+        // otherwise the next parse with this parser would resume this one
+        parser.reset();
+        if timeout_micros > 0 {
+            return Err(FormatError::Timeout);
+        }
+        return Err(FormatError::ParseError(Vec::new()));
+    };
+    Ok(tree)
+}
+
+fn check_syntax(tree: &tree_sitter::Tree, input: &str) -> Result<(), FormatError> {
+    let errors = collect_syntax_errors(tree.root_node(), input.as_bytes());
+    if !errors.is_empty() {
+        return Err(FormatError::ParseError(errors));
+    }
+    Ok(())
+}
+
+/// `original` as the tree walk sees it, with `\n` line endings and without a byte order mark,
+/// and whether it started with one.
+fn strip_bom_and_crlf(original: &str) -> (bool, Cow<'_, str>) {
+    let (bom, input) = match original.strip_prefix('\u{FEFF}') {
+        Some(input) => (true, input),
+        None => (false, original),
+    };
+    let input = if input.contains('\r') {
+        Cow::Owned(input.replace("\r\n", "\n"))
+    } else {
+        Cow::Borrowed(input)
+    };
+    (bom, input)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FormatReport {
+    pub output: String,
+    /// Whether `output` differs from the input.
+    pub changed: bool,
+    pub line_count: usize,
+    /// Deepest nesting of block tags in the template.
+    pub block_depth_max: usize,
+    /// Problems that didn't stop formatting, such as an `{% extends %}` after other content.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    /// With `best_effort`, the syntax errors whose source was copied to `output` as it is.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<SyntaxError>,
+}
+
+/// The formatted template as it is being built.
+#[derive(Default)]
+struct Output {
+    /// The output not yet handed to the [`Sink`].
+    text: String,
+    /// Whether some output was handed to the [`Sink`].
+    flushed: bool,
+    /// Whether an expression or text can continue the current line.
+    inline: bool,
+    /// Blank lines to emit before the next line.
+    blank_lines: usize,
+    trim_trailing_whitespace: bool,
+}
+
+impl Output {
+    fn new_line(&mut self, indent: &str) {
+        if self.trim_trailing_whitespace {
+            self.text
+                .truncate(self.text.trim_end_matches([' ', '\t']).len());
+        }
+        for _ in 0..self.blank_lines {
+            self.text.push('\n');
+        }
+        self.blank_lines = 0;
+        self.text.push('\n');
+        self.text.push_str(indent);
+    }
+
+    /// Whether nothing was output yet.
+    fn is_empty(&self) -> bool {
+        !self.flushed && self.text.is_empty()
+    }
+
+    /// Hands the lines of `text` before the last one holding anything but whitespace to `sink`.
+    /// They don't change any more, unlike the whitespace ending a line or the template.
+    fn flush(&mut self, sink: &mut Sink) -> Result<(), FormatError> {
+        let Some(end) = self.text.trim_end().rfind('\n') else {
+            return Ok(());
+        };
+        sink.write(&self.text[..=end])?;
+        self.text.drain(..=end);
+        self.flushed = true;
+        Ok(())
+    }
+}
+
+/// Where the formatted template goes as its lines are completed, with the line endings and byte
+/// order mark it gets in the end.
+struct Sink<'a> {
+    write: &'a mut dyn FnMut(&str) -> Result<(), FormatError>,
+    crlf: bool,
+    /// Whether to start with a byte order mark.
+    bom: bool,
+    /// Whether newlines starting the output are dropped.
+    trim_start: bool,
+    /// Whether anything was written.
+    started: bool,
+}
+
+impl<'a> Sink<'a> {
+    fn new(write: &'a mut dyn FnMut(&str) -> Result<(), FormatError>) -> Self {
+        Sink {
+            write,
+            crlf: false,
+            bom: false,
+            trim_start: true,
+            started: false,
+        }
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), FormatError> {
+        let text = if self.started || !self.trim_start {
+            text
+        } else {
+            text.trim_start_matches('\n')
+        };
+        if text.is_empty() {
+            return Ok(());
+        }
+        if !self.started {
+            self.started = true;
+            if std::mem::take(&mut self.bom) {
+                (self.write)("\u{FEFF}")?;
+            }
+        }
+        if self.crlf {
+            (self.write)(&text.replace('\n', "\r\n"))
+        } else {
+            (self.write)(text)
+        }
+    }
+
+    /// Writes the byte order mark of an empty template.
+    fn finish(&mut self) -> Result<(), FormatError> {
+        if std::mem::take(&mut self.bom) {
+            (self.write)("\u{FEFF}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A block tag waiting for its end tag.
+struct OpenBlock {
+    keyword: String,
+    start: tree_sitter::Point,
+}
+
+fn unbalanced(
+    expected: Option<String>,
+    found: Option<String>,
+    node: tree_sitter::Node,
+) -> FormatError {
+    let start = node.start_position();
+    FormatError::UnbalancedBlock {
+        expected,
+        found,
+        line: start.row + 1,
+        column: start.column + 1,
+    }
+}
+
+/// The report on formatting `original` into `formatted`, from the `tree` of its `input`.
+fn report(
+    original: &str,
+    formatted: String,
+    tree: &tree_sitter::Tree,
+    input: &str,
+    block_depth_max: usize,
+) -> FormatReport {
+    let warnings = extends_warnings(tree.root_node(), input.as_bytes());
+    FormatReport {
+        errors: collect_syntax_errors(tree.root_node(), input.as_bytes()),
+        ..FormatReport::new(original, formatted, block_depth_max, warnings)
+    }
+}
+
+impl FormatReport {
+    fn new(input: &str, output: String, block_depth_max: usize, warnings: Vec<String>) -> Self {
+        FormatReport {
+            changed: output != input,
+            line_count: output.lines().count(),
+            block_depth_max,
+            output,
+            warnings,
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[repr(transparent)]
+pub struct Formatter {
+    options: FormatOptions,
+}
+
+impl Formatter {
+    pub fn new(options: FormatOptions) -> Self {
+        Formatter { options }
+    }
+
+    /// `options` as a formatter, borrowed instead of cloned into [`Formatter::new`].
+    pub(crate) fn borrowed(options: &FormatOptions) -> &Formatter {
+        // SAFETY: `Formatter` is a `repr(transparent)` wrapper of `FormatOptions`
+        unsafe { &*(options as *const FormatOptions).cast::<Formatter>() }
+    }
+
+    pub fn builder() -> FormatterBuilder {
+        FormatterBuilder::default()
+    }
+
+    pub fn options(&self) -> &FormatOptions {
+        &self.options
+    }
+
+    fn peek_custom_block_keyword(&self, node: tree_sitter::Node, source: &[u8]) -> Option<String> {
+        if node.kind() != "statement" {
+            return None;
+        }
+        let text = stmt_keyword_node(node)?.utf8_text(source).ok()?;
+        self.options
+            .custom_blocks
+            .iter()
+            .any(|(open, close)| open == text || close == text)
+            .then(|| text.to_string())
+    }
+
+    fn closing_keyword(&self, open: &str) -> String {
+        self.options
+            .custom_blocks
+            .iter()
+            .find(|(custom_open, _)| custom_open == open)
+            .map_or_else(|| format!("end{}", open), |(_, close)| close.clone())
+    }
+
+    /// Pops the innermost open block if `keyword` is its end tag.
+    fn close_block(
+        &self,
+        blocks: &mut Vec<OpenBlock>,
+        keyword: String,
+        node: tree_sitter::Node,
+    ) -> Result<(), FormatError> {
+        let expected = blocks
+            .last()
+            .map(|open| self.closing_keyword(&open.keyword));
+        if expected.as_deref() != Some(keyword.as_str()) {
+            return Err(unbalanced(expected, Some(keyword), node));
+        }
+        blocks.pop();
+        Ok(())
+    }
+
+    /// The indentation of `level`, borrowed from a run of spaces or tabs unless it is deeper
+    /// than any template nests in practice.
+    fn indent(&self, level: usize) -> Cow<'static, str> {
+        let (run, width) = match self.options.indent_style {
+            IndentStyle::Spaces => (SPACES, level * self.options.indent_size),
+            IndentStyle::Tabs => (TABS, level),
+        };
+        match run.get(..width) {
+            Some(indent) => Cow::Borrowed(indent),
+            None => Cow::Owned(run[..1].repeat(width)),
+        }
+    }
+
+    /// Formats a tree parsed from `source`. Fails with [`FormatError::Utf8`] rather than
+    /// panicking if `source` isn't valid UTF-8.
+    pub fn format_jinja_node(
+        &self,
+        root_node: tree_sitter::Node,
+        source: &[u8],
+    ) -> Result<String, FormatError> {
+        let mut formatted = String::with_capacity(output_capacity(source.len()));
+        let mut write = |text: &str| {
+            formatted.push_str(text);
+            Ok(())
+        };
+        self.format_tree(root_node, source, &mut Sink::new(&mut write))?;
+        Ok(formatted)
+    }
+
+    /// Formats the tree into `sink` and returns the deepest block nesting seen.
+    fn format_tree(
+        &self,
+        root_node: tree_sitter::Node,
+        source: &[u8],
+        sink: &mut Sink,
+    ) -> Result<usize, FormatError> {
+        if root_node.child_count() == 0 && !self.options.indent_html {
+            // without any tags there is nothing to indent, the text is kept byte-for-byte
+            let text = utf8(source)?;
+            if !text.trim().is_empty() {
+                // the trailing newlines are the mode's, blank lines of whitespace included
+                let mut text = text.trim_end().to_string();
+                if self.options.trim_trailing_whitespace {
+                    text = trim_line_ends(&text);
+                }
+                sink.trim_start = false;
+                self.end_output(&text, source, sink)?;
+            }
+            sink.finish()?;
+            return Ok(0);
+        }
+        let mut out = Output {
+            trim_trailing_whitespace: self.options.trim_trailing_whitespace,
+            ..Output::default()
+        };
+        // dfs
+        let mut blocks: Vec<OpenBlock> = Vec::new();
+        let mut block_depth_max = 0;
+        // end of the last node copied to the output, the text after it isn't part of the tree
+        let mut text_start = 0;
+        // `{% set %}` assignments on consecutive lines, to be aligned
+        let mut assignments = Vec::new();
+        // open elements, also without `indent_html` to keep the text of `<pre>` as it is
+        let mut html = Html::new(self.options.indent_html);
+        // whether a node with syntax errors was copied, with `best_effort`
+        let mut copied_errors = false;
+
+        let mut i = 0;
+        while i < root_node.child_count() {
+            // assignments still to be aligned are found by their offset in the output
+            if assignments.is_empty() {
+                out.flush(sink)?;
+            }
+            let node = root_node.child(i).unwrap();
+            i += 1;
+            let text = utf8(&source[text_start..node.start_byte()])?;
+            if !text.trim().is_empty() || text.matches('\n').count() > 1 {
+                align_assignments(&mut out.text, &mut assignments);
+            }
+            self.push_text(&mut out, text, blocks.len(), &mut html);
+            if self.options.best_effort && !collect_syntax_errors(node, source).is_empty() {
+                // copied as it is, from where it starts in the source
+                align_assignments(&mut out.text, &mut assignments);
+                if !out.inline {
+                    if let Some((_, indent)) = text.rsplit_once('\n') {
+                        out.new_line(indent);
+                    }
+                }
+                out.text.push_str(utf8(&source[node.byte_range()])?);
+                out.inline = true;
+                copied_errors = true;
+                text_start = node.end_byte();
+                continue;
+            }
+            let html_depth = html.depth();
+            let keyword = self.peek_keyword(node, source);
+            if keyword.as_deref() == Some("raw") {
+                align_assignments(&mut out.text, &mut assignments);
+                let end = (i..root_node.child_count())
+                    .find(|&j| {
+                        let child = root_node.child(j).unwrap();
+                        peek_jinja_stmt_keyword(child, source).as_deref() == Some("endraw")
+                    })
+                    .ok_or_else(|| unbalanced(Some("endraw".to_string()), None, node))?;
+                let end_node = root_node.child(end).unwrap();
+                let level = blocks.len() + html_depth;
+                self.push_verbatim(&mut out, level, node, Some((level, end_node)), source)?;
+                i = end + 1;
+                text_start = end_node.end_byte();
+                continue;
+            }
+            if fmt_directive(node, source) == Some("off") {
+                align_assignments(&mut out.text, &mut assignments);
+                let end = (i..root_node.child_count())
+                    .find(|&j| fmt_directive(root_node.child(j).unwrap(), source) == Some("on"));
+                let level = blocks.len() + html_depth;
+                // the region is copied as is, but its tags still open and close blocks
+                for j in i..end.unwrap_or(root_node.child_count()) {
+                    let child = root_node.child(j).unwrap();
+                    match self.peek_keyword(child, source) {
+                        Some(keyword) if keyword != "raw" && keyword != "endraw" => {
+                            let branch = is_branch(&keyword);
+                            self.track_block(&mut blocks, keyword, child)?;
+                            block_depth_max = block_depth_max.max(blocks.len());
+                            html.block_tag(blocks.len(), branch);
+                        }
+                        _ => {}
+                    }
+                }
+                let close = end.map(|end| {
+                    let level = blocks.len() + html.depth();
+                    (level, root_node.child(end).unwrap())
+                });
+                self.push_verbatim(&mut out, level, node, close, source)?;
+                i = end.map_or(root_node.child_count(), |end| end + 1);
+                text_start = close.map_or(source.len(), |(_, close)| close.end_byte());
+                continue;
+            }
+            let has_filters = node.kind() == "expression" || keyword.as_deref() == Some("filter");
+            let has_signature = matches!(keyword.as_deref(), Some("macro" | "call"));
+            // inline `{% set %}` tags have no block keyword
+            let is_statement = node.kind() == "statement" && keyword.is_none();
+            let curr_ident = match keyword {
+                Some(keyword) => {
+                    let branch = is_branch(&keyword);
+                    let level = self.track_block(&mut blocks, keyword, node)?;
+                    level + html.block_tag(blocks.len(), branch)
+                }
+                None => blocks.len() + html_depth,
+            };
+            block_depth_max = block_depth_max.max(blocks.len());
+
+            // expressions stay on the line of the text or expression before them, and so does
+            // anything in a `<pre>` or `<script>`
+            let preformatted = html.preformatted();
+            let starts_line = !preformatted && (node.kind() != "expression" || !out.inline);
+            if starts_line {
+                out.new_line(&self.indent(curr_ident));
+            }
+
+            let raw_text = node.utf8_text(source).map_err(|_| FormatError::Utf8)?;
+            let mut operators = Vec::new();
+            if has_filters && self.options.pipe_spacing != PipeSpacing::Preserve {
+                let padding = match self.options.pipe_spacing {
+                    PipeSpacing::Tight => Padding::Tight,
+                    _ => Padding::Spaced,
+                };
+                operators.extend(
+                    filter_pipes(node, source)
+                        .into_iter()
+                        .map(|pipe| (pipe..pipe + 1, padding)),
+                );
+            }
+            let has_operators = matches!(node.kind(), "expression" | "statement");
+            let literals = match self.options.trailing_comma {
+                TrailingComma::Always | TrailingComma::Never if has_operators => {
+                    inline_literals(node, source)
+                }
+                _ => Vec::new(),
+            };
+            let mut removed_commas = Vec::new();
+            for literal in literals {
+                match (self.options.trailing_comma, literal.trailing_comma) {
+                    (TrailingComma::Never, Some(comma)) => removed_commas.push(comma),
+                    (TrailingComma::Always, None) => {
+                        operators.push((literal.close..literal.close, Padding::Added))
+                    }
+                    _ => {}
+                }
+            }
+            if has_operators && self.options.comma_spacing {
+                operators.extend(
+                    commas(node, source)
+                        .into_iter()
+                        .filter(|comma| !removed_commas.contains(comma))
+                        .map(|comma| (comma..comma + 1, Padding::Trailing)),
+                );
+            }
+            operators.extend(
+                removed_commas
+                    .into_iter()
+                    .map(|comma| (comma..comma + 1, Padding::Removed)),
+            );
+            if node.kind() == "statement" {
+                operators
+                    .extend(do_keyword(node, source).map(|keyword| (keyword, Padding::Spaced)));
+            }
+            if has_signature {
+                let default_padding = if self.options.macro_default_spacing {
+                    Padding::Spaced
+                } else {
+                    Padding::Tight
+                };
+                operators.extend(signature(node, source, default_padding));
+            }
+            if has_operators && self.options.operator_spacing {
+                operators.extend(
+                    binary_operators(node, source)
+                        .into_iter()
+                        .map(|operator| (operator, Padding::Spaced)),
+                );
+            }
+            operators.sort_by_key(|(operator, _)| operator.start);
+            let tag = normalize_operator_spacing(raw_text, &operators);
+            let tag = match node.kind() {
+                "expression" | "statement" => normalize_quotes(&tag, self.options.quote_style),
+                _ => tag,
+            };
+            let mut tag = normalize_tag_spacing(&tag);
+            // the last line keeps its whitespace, so a tag on one line has nothing to trim
+            if self.options.trim_trailing_whitespace && tag.contains('\n') {
+                tag = trim_tag_line_ends(&tag);
+            }
+            let assignment = (self.options.align_set_assignments && is_statement)
+                .then(|| set_assignment(&tag))
+                .flatten();
+            if let Some((assignment, _, _)) = &assignment {
+                tag = assignment.clone();
+            }
+            let wrapped = starts_line
+                .then(|| self.break_long_tag(&tag, curr_ident))
+                .flatten();
+            match (&assignment, &wrapped) {
+                (Some((_, target_end, column)), None) => {
+                    assignments.push((out.text.len() + target_end, *column));
+                }
+                _ => align_assignments(&mut out.text, &mut assignments),
+            }
+            out.text.push_str(wrapped.as_deref().unwrap_or(&tag));
+
+            out.inline = node.kind() == "expression" || preformatted;
+            text_start = node.end_byte();
+        }
+        align_assignments(&mut out.text, &mut assignments);
+        let rest = utf8(&source[text_start..])?;
+        self.push_text(&mut out, rest, blocks.len(), &mut html);
+        // the end tag might be in a node with syntax errors
+        if let Some(open) = blocks.last().filter(|_| !copied_errors) {
+            return Err(FormatError::UnbalancedBlock {
+                expected: Some(self.closing_keyword(&open.keyword)),
+                found: None,
+                line: open.start.row + 1,
+                column: open.start.column + 1,
+            });
+        }
+        self.end_output(out.text.trim_end(), source, sink)?;
+        sink.finish()?;
+        Ok(block_depth_max)
+    }
+
+    /// Writes the last of the output, `rest`, followed by the trailing newlines of the
+    /// configured mode.
+    fn end_output(&self, rest: &str, source: &[u8], sink: &mut Sink) -> Result<(), FormatError> {
+        let trailing_newlines = match self.options.trailing_newline {
+            TrailingNewline::Insert => 1,
+            TrailingNewline::Remove => 0,
+            // the newlines in the whitespace ending the source, blank lines included
+            TrailingNewline::Preserve => source
+                .iter()
+                .rev()
+                .take_while(|byte| byte.is_ascii_whitespace())
+                .filter(|&&byte| byte == b'\n')
+                .count(),
+        };
+        sink.write(rest)?;
+        if sink.started && trailing_newlines > 0 {
+            sink.write(&"\n".repeat(trailing_newlines))?;
+        }
+        Ok(())
+    }
+
+    /// Emits the text between two nodes. Lines that start with text are copied as they are,
+    /// only the text sharing a line with a preceding statement is moved to a line of its own at
+    /// `level`. With `indent_html`, lines are instead indented by `level` and the HTML elements
+    /// open at their start. Runs of blank lines, including the ones separating two tags, are
+    /// capped at `max_blank_lines`, except in preformatted elements such as `<pre>`.
+    fn push_text(&self, out: &mut Output, text: &str, level: usize, html: &mut Html) {
+        // the indentation of a line starting with text, if it is replaced
+        let line_indent = |line: &str, html: &mut Html| {
+            let indent = html
+                .line_depth(line)
+                .map(|depth| self.indent(level + depth));
+            html.scan(line);
+            indent
+        };
+        let mut lines = text.split('\n');
+        let first = lines.next().unwrap_or_default();
+        if out.inline {
+            line_indent(first, html);
+            out.text.push_str(first);
+        } else if !first.trim().is_empty() {
+            let indent = line_indent(first, html);
+            // the start of the template is the start of a line like any other
+            let at_start = out.is_empty() && indent.is_none();
+            out.new_line(&indent.clone().unwrap_or_else(|| self.indent(level)));
+            out.text
+                .push_str(if at_start { first } else { first.trim_start() });
+            out.inline = true;
+        } else {
+            line_indent(first, html);
+        }
+
+        let mut lines = lines.peekable();
+        let mut blank_lines = 0;
+        while let Some(line) = lines.next() {
+            html.scan("\n");
+            // whether the next node continues the last line
+            out.inline = !line.trim().is_empty();
+            if html.preformatted() {
+                // blank lines and the indentation of the next node are kept too
+                out.blank_lines = blank_lines.min(self.options.max_blank_lines);
+                blank_lines = 0;
+                out.new_line("");
+                line_indent(line, html);
+                out.text.push_str(line);
+                out.inline = true;
+                continue;
+            }
+            if line.trim().is_empty() {
+                line_indent(line, html);
+                // the last line only holds the indentation of the next node
+                if lines.peek().is_some() {
+                    blank_lines += 1;
+                }
+                continue;
+            }
+            out.blank_lines = blank_lines.min(self.options.max_blank_lines);
+            blank_lines = 0;
+            match line_indent(line, html) {
+                Some(indent) => {
+                    out.new_line(&indent);
+                    out.text.push_str(line.trim_start());
+                }
+                None => {
+                    out.new_line("");
+                    out.text.push_str(line);
+                }
+            }
+        }
+        out.blank_lines = blank_lines.min(self.options.max_blank_lines);
+    }
+
+    /// Spreads a tag starting a line at `level` over several lines if that line would be longer
+    /// than `max_line_width`.
+    fn break_long_tag(&self, tag: &str, level: usize) -> Option<String> {
+        let max_width = self.options.max_line_width;
+        if max_width == 0 {
+            return None;
+        }
+        let indent = self.indent(level);
+        if indent.chars().count() + tag.chars().count() <= max_width {
+            return None;
+        }
+        if self.options.expand_literals {
+            let trailing_comma = self.options.trailing_comma != TrailingComma::Never;
+            if let Some(expanded) = expand_literal(tag, &indent, &self.indent(1), trailing_comma) {
+                return Some(expanded);
+            }
+        }
+        wrap_tag(tag, &self.indent(level + 1))
+    }
+
+    fn peek_keyword(&self, node: tree_sitter::Node, source: &[u8]) -> Option<String> {
+        peek_jinja_stmt_keyword(node, source)
+            .or_else(|| self.peek_custom_block_keyword(node, source))
+    }
+
+    /// Updates the open blocks for a block tag and returns the indent level of its line.
+    fn track_block(
+        &self,
+        blocks: &mut Vec<OpenBlock>,
+        keyword: String,
+        node: tree_sitter::Node,
+    ) -> Result<usize, FormatError> {
+        let open = |blocks: &mut Vec<OpenBlock>, keyword| {
+            blocks.push(OpenBlock {
+                keyword,
+                start: node.start_position(),
+            });
+            blocks.len() - 1
+        };
+        match keyword.as_str() {
+            "if" | "for" | "macro" | "call" | "filter" | "block" | "set" | "with"
+            | "autoescape" | "trans" | "apply" => Ok(open(blocks, keyword)),
+            "elif" | "else" | "pluralize" => {
+                let parents: &[&str] = match keyword.as_str() {
+                    "elif" => &["if"],
+                    // `{% for %}...{% else %}` renders when the loop is empty
+                    "else" => &["if", "for"],
+                    _ => &["trans"],
+                };
+                match blocks.last() {
+                    Some(open) if parents.contains(&open.keyword.as_str()) => Ok(blocks.len() - 1),
+                    open => Err(unbalanced(
+                        open.map(|open| self.closing_keyword(&open.keyword)),
+                        Some(keyword),
+                        node,
+                    )),
+                }
+            }
+            "endif" | "endfor" | "endmacro" | "endcall" | "endfilter" | "endblock" | "endset"
+            | "endwith" | "endautoescape" | "endtrans" | "endapply" => {
+                self.close_block(blocks, keyword, node)?;
+                Ok(blocks.len())
+            }
+            "endraw" => Err(unbalanced(None, Some(keyword), node)),
+            _ if self
+                .options
+                .custom_blocks
+                .iter()
+                .any(|(open, _)| *open == keyword) =>
+            {
+                Ok(open(blocks, keyword))
+            }
+            _ if self
+                .options
+                .custom_blocks
+                .iter()
+                .any(|(_, close)| *close == keyword) =>
+            {
+                self.close_block(blocks, keyword, node)?;
+                Ok(blocks.len())
+            }
+            _ => Err(FormatError::UnknownKeyword(keyword)),
+        }
+    }
+
+    /// Emits `open` at `level` followed by the source between it and `close`, byte-for-byte.
+    /// Only the indentation of `close` is replaced when it sits on its own line. Without a
+    /// `close` the rest of the template is copied.
+    fn push_verbatim(
+        &self,
+        out: &mut Output,
+        level: usize,
+        open: tree_sitter::Node,
+        close: Option<(usize, tree_sitter::Node)>,
+        source: &[u8],
+    ) -> Result<(), FormatError> {
+        out.new_line(&self.indent(level));
+        out.inline = false;
+        let formatted = &mut out.text;
+        formatted.push_str(&normalize_tag_spacing(utf8(&source[open.byte_range()])?));
+
+        let Some((close_level, close)) = close else {
+            formatted.push_str(utf8(&source[open.end_byte()..])?.trim_end());
+            return Ok(());
+        };
+        let body = utf8(&source[open.end_byte()..close.start_byte()])?;
+        match body.rfind('\n') {
+            Some(last_newline) if body[last_newline + 1..].trim().is_empty() => {
+                formatted.push_str(&body[..=last_newline]);
+                formatted.push_str(&self.indent(close_level));
+            }
+            _ => formatted.push_str(body),
+        }
+        formatted.push_str(&normalize_tag_spacing(utf8(&source[close.byte_range()])?));
+        Ok(())
+    }
+
+    /// Formats the template with its line endings turned into `\n` and back into the configured
+    /// ones, since the tree walk only knows about `\n`. A leading byte order mark is left out,
+    /// and put back only with `preserve_bom`. The report compares against `original`.
+    fn format_source(
+        &self,
+        parser: &mut tree_sitter::Parser,
+        original: &str,
+    ) -> Result<FormatReport, FormatError> {
+        let mut formatted = String::with_capacity(output_capacity(original.len()));
+        let mut write = |text: &str| {
+            formatted.push_str(text);
+            Ok(())
+        };
+        let (tree, input, block_depth_max) = self.format_into(parser, original, &mut write)?;
+        Ok(report(original, formatted, &tree, &input, block_depth_max))
+    }
+
+    /// Formats `original` into `write`, returning the tree, the input it was parsed from and the
+    /// deepest block nesting.
+    fn format_into<'a>(
+        &self,
+        parser: &mut tree_sitter::Parser,
+        original: &'a str,
+        write: &mut dyn FnMut(&str) -> Result<(), FormatError>,
+    ) -> Result<(tree_sitter::Tree, Cow<'a, str>, usize), FormatError> {
+        let (bom, input) = strip_bom_and_crlf(original);
+        let tree = parse(
+            parser,
+            &input,
+            self.options.parse_timeout_micros,
+            self.options.best_effort,
+        )?;
+        let block_depth_max = self.write_tree(&tree, original, &input, bom, write)?;
+        Ok((tree, input, block_depth_max))
+    }
+
+    /// Formats `tree`, parsed from `input`, into `write` with the line endings and byte order
+    /// mark that `original` calls for.
+    fn write_tree(
+        &self,
+        tree: &tree_sitter::Tree,
+        original: &str,
+        input: &str,
+        bom: bool,
+        write: &mut dyn FnMut(&str) -> Result<(), FormatError>,
+    ) -> Result<usize, FormatError> {
+        let crlf = match self.options.line_ending {
+            LineEnding::Auto => is_mostly_crlf(original),
+            LineEnding::Lf => false,
+            LineEnding::Crlf => true,
+        };
+        let mut sink = Sink {
+            crlf,
+            bom: bom && self.options.preserve_bom,
+            ..Sink::new(write)
+        };
+        self.format_tree(tree.root_node(), input.as_bytes(), &mut sink)
+    }
+
+    /// Parses `input` as formatting does, with the bodies of `{% raw %}` blocks blanked out.
+    /// Syntax errors are left in the tree as `ERROR` and missing nodes.
+    pub fn parse(&self, input: &str) -> Result<tree_sitter::Tree, FormatError> {
+        with_parser(|parser| parse(parser, input, self.options.parse_timeout_micros, true))
+    }
+
+    /// The syntax errors of `input`, without formatting it. Fails only if parsing does, e.g.
+    /// by timing out.
+    pub fn syntax_errors(&self, input: &str) -> Result<Vec<SyntaxError>, FormatError> {
+        let input = input.strip_prefix('\u{FEFF}').unwrap_or(input);
+        let tree = self.parse(input)?;
+        Ok(collect_syntax_errors(tree.root_node(), input.as_bytes()))
+    }
+
+    /// Parses `input` into a [`ParsedTemplate`] to keep up to date with [`Formatter::reparse`]
+    /// while the template is edited and to format with [`Formatter::format_parsed`]. Syntax
+    /// errors are left in the tree, formatting reports them.
+    pub fn parse_template(&self, input: &str) -> Result<ParsedTemplate, FormatError> {
+        let (_, normalized) = strip_bom_and_crlf(input);
+        let masked = mask_raw_blocks(&normalized);
+        let tree = with_parser(|parser| {
+            parse_masked(parser, &masked, None, self.options.parse_timeout_micros)
+        })?;
+        Ok(ParsedTemplate {
+            input: normalized.into_owned(),
+            source: input.to_string(),
+            masked,
+            tree,
+        })
+    }
+
+    /// Replaces the bytes `range` of the template's source with `text` and parses it again,
+    /// reusing the parts of its tree that the edit didn't touch. If parsing fails, e.g. by
+    /// timing out, the template is left as it was.
+    ///
+    /// # Panics
+    ///
+    /// Like [`String::replace_range`], if `range` isn't on char boundaries of the source.
+    pub fn reparse(
+        &self,
+        template: &mut ParsedTemplate,
+        range: Range<usize>,
+        text: &str,
+    ) -> Result<(), FormatError> {
+        let mut source = template.source.clone();
+        source.replace_range(range, text);
+        let (_, normalized) = strip_bom_and_crlf(&source);
+        let masked = mask_raw_blocks(&normalized);
+        let mut old_tree = template.tree.clone();
+        old_tree.edit(&input_edit(&template.masked, &masked));
+        let tree = with_parser(|parser| {
+            parse_masked(
+                parser,
+                &masked,
+                Some(&old_tree),
+                self.options.parse_timeout_micros,
+            )
+        })?;
+        *template = ParsedTemplate {
+            input: normalized.into_owned(),
+            source,
+            masked,
+            tree,
+        };
+        Ok(())
+    }
+
+    /// Like [`Formatter::format_report`], with the tree of `template` instead of parsing it. The
+    /// whole template is formatted all the same, the indentation of a line depends on every block
+    /// before it.
+    pub fn format_parsed(&self, template: &ParsedTemplate) -> Result<FormatReport, FormatError> {
+        if !self.options.best_effort {
+            check_syntax(&template.tree, &template.input)?;
+        }
+        let source = &template.source;
+        let mut formatted = String::with_capacity(output_capacity(source.len()));
+        let mut write = |text: &str| {
+            formatted.push_str(text);
+            Ok(())
+        };
+        let bom = source.starts_with('\u{FEFF}');
+        let block_depth_max =
+            self.write_tree(&template.tree, source, &template.input, bom, &mut write)?;
+        Ok(report(
+            source,
+            formatted,
+            &template.tree,
+            &template.input,
+            block_depth_max,
+        ))
+    }
+
+    pub fn format(&self, input: &str) -> Result<String, FormatError> {
+        with_parser(|parser| self.format_with_parser(parser, input))
+    }
+
+    /// Like [`Formatter::format`], which reuses a parser of its thread, but with a parser from
+    /// [`new_parser`] of the caller's.
+    pub fn format_with_parser(
+        &self,
+        parser: &mut tree_sitter::Parser,
+        input: &str,
+    ) -> Result<String, FormatError> {
+        self.format_source(parser, input)
+            .map(|report| report.output)
+    }
+
+    /// Like [`Formatter::format`], but writes the formatted template to `writer` as it goes
+    /// instead of returning it, so that it is never held in memory as a whole. Writes are
+    /// small, a `BufWriter` is best. If formatting fails part way, `writer` has had the output
+    /// up to that point.
+    pub fn format_to_writer(
+        &self,
+        input: &str,
+        writer: &mut impl std::io::Write,
+    ) -> Result<(), FormatError> {
+        let mut write = |text: &str| {
+            writer
+                .write_all(text.as_bytes())
+                .map_err(|err| FormatError::Io(err.to_string()))
+        };
+        with_parser(|parser| self.format_into(parser, input, &mut write))?;
+        Ok(())
+    }
+
+    /// Formats `input` and describes the result.
+    pub fn format_report(&self, input: &str) -> Result<FormatReport, FormatError> {
+        with_parser(|parser| self.format_source(parser, input))
+    }
+
+    /// Formats only `lines` (1-based, inclusive) of `input`. Indentation still follows the
+    /// blocks opened before the range, and every line outside it is kept byte-for-byte.
+    pub fn format_range(
+        &self,
+        input: &str,
+        lines: RangeInclusive<usize>,
+    ) -> Result<String, FormatError> {
+        self.format_range_report(input, lines)
+            .map(|report| report.output)
+    }
+
+    /// Like [`Formatter::format_report`], for [`Formatter::format_range`].
+    pub fn format_range_report(
+        &self,
+        input: &str,
+        lines: RangeInclusive<usize>,
+    ) -> Result<FormatReport, FormatError> {
+        let report = with_parser(|parser| self.format_source(parser, input))?;
+        let output = splice_lines(input, &report.output, lines);
+        Ok(FormatReport {
+            errors: report.errors,
+            ..FormatReport::new(input, output, report.block_depth_max, report.warnings)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FormatterBuilder {
+    options: FormatOptions,
+}
+
+impl FormatterBuilder {
+    pub fn indent_size(mut self, indent_size: usize) -> Self {
+        self.options.indent_size = indent_size;
+        self
+    }
+
+    pub fn indent_style(mut self, indent_style: IndentStyle) -> Self {
+        self.options.indent_style = indent_style;
+        self
+    }
+
+    pub fn trailing_newline(mut self, trailing_newline: TrailingNewline) -> Self {
+        self.options.trailing_newline = trailing_newline;
+        self
+    }
+
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.options.line_ending = line_ending;
+        self
+    }
+
+    pub fn max_blank_lines(mut self, max_blank_lines: usize) -> Self {
+        self.options.max_blank_lines = max_blank_lines;
+        self
+    }
+
+    pub fn pipe_spacing(mut self, pipe_spacing: PipeSpacing) -> Self {
+        self.options.pipe_spacing = pipe_spacing;
+        self
+    }
+
+    pub fn quote_style(mut self, quote_style: QuoteStyle) -> Self {
+        self.options.quote_style = quote_style;
+        self
+    }
+
+    pub fn comma_spacing(mut self, comma_spacing: bool) -> Self {
+        self.options.comma_spacing = comma_spacing;
+        self
+    }
+
+    pub fn macro_default_spacing(mut self, macro_default_spacing: bool) -> Self {
+        self.options.macro_default_spacing = macro_default_spacing;
+        self
+    }
+
+    pub fn indent_html(mut self, indent_html: bool) -> Self {
+        self.options.indent_html = indent_html;
+        self
+    }
+
+    pub fn operator_spacing(mut self, operator_spacing: bool) -> Self {
+        self.options.operator_spacing = operator_spacing;
+        self
+    }
+
+    pub fn max_line_width(mut self, max_line_width: usize) -> Self {
+        self.options.max_line_width = max_line_width;
+        self
+    }
+
+    pub fn expand_literals(mut self, expand_literals: bool) -> Self {
+        self.options.expand_literals = expand_literals;
+        self
+    }
+
+    pub fn trailing_comma(mut self, trailing_comma: TrailingComma) -> Self {
+        self.options.trailing_comma = trailing_comma;
+        self
+    }
+
+    pub fn align_set_assignments(mut self, align_set_assignments: bool) -> Self {
+        self.options.align_set_assignments = align_set_assignments;
+        self
+    }
+
+    pub fn trim_trailing_whitespace(mut self, trim_trailing_whitespace: bool) -> Self {
+        self.options.trim_trailing_whitespace = trim_trailing_whitespace;
+        self
+    }
+
+    pub fn preserve_bom(mut self, preserve_bom: bool) -> Self {
+        self.options.preserve_bom = preserve_bom;
+        self
+    }
+
+    pub fn best_effort(mut self, best_effort: bool) -> Self {
+        self.options.best_effort = best_effort;
+        self
+    }
+
+    pub fn parse_timeout_micros(mut self, parse_timeout_micros: u64) -> Self {
+        self.options.parse_timeout_micros = parse_timeout_micros;
+        self
+    }
+
+    pub fn custom_blocks(mut self, custom_blocks: Vec<(String, String)>) -> Self {
+        self.options.custom_blocks = custom_blocks;
+        self
+    }
+
+    pub fn build(self) -> Formatter {
+        Formatter::new(self.options)
+    }
+}
@@ -0,0 +1,269 @@
+//! A lightweight matcher of the HTML tags in the text between Jinja nodes. It keeps track of
+//! which block-level elements are open, for `FormatOptions::indent_html`, and of whether the
+//! text is in a preformatted element such as `<pre>`. It doesn't validate anything.
+
+/// Elements whose content is indented. Inline elements such as `<a>` and `<span>`, and void
+/// elements such as `<br>` and `<img>`, don't affect indentation.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "details",
+    "dialog",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "head",
+    "header",
+    "hgroup",
+    "html",
+    "li",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "optgroup",
+    "option",
+    "p",
+    "section",
+    "select",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "template",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// Elements that an opening tag of the same name closes when one is innermost, e.g. `<li>` after
+/// an `<li>` without `</li>`.
+const IMPLICITLY_CLOSED: &[&str] = &["dd", "dt", "li", "option", "p", "td", "th", "tr"];
+
+/// Elements holding text that isn't markup, so `<` in them doesn't start a tag.
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "textarea"];
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Text,
+    Tag(Tag),
+    Comment,
+    /// Inside the element with this name, waiting for its closing tag.
+    RawText(String),
+}
+
+#[derive(Default)]
+struct Tag {
+    name: String,
+    closing: bool,
+    /// Whether the name has ended and the attributes, if any, have started.
+    name_done: bool,
+    quote: Option<char>,
+    /// Whether the last character seen outside of quotes was `/`, as in `<br/>`.
+    self_closing: bool,
+}
+
+pub(crate) struct Html {
+    /// Whether open elements add to the indentation.
+    indent: bool,
+    /// Names of the open block-level elements, innermost last.
+    open: Vec<String>,
+    state: State,
+    /// Number of open `<pre>` elements.
+    pre: usize,
+    /// For each open Jinja block, the elements open at its start and, once it has an `elif` or
+    /// `else`, the ones open at the end of its first branch.
+    blocks: Vec<(Vec<String>, Option<Vec<String>>)>,
+}
+
+impl Html {
+    pub(crate) fn new(indent: bool) -> Self {
+        Html {
+            indent,
+            open: Vec::new(),
+            state: State::Text,
+            pre: 0,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// The indent level, in elements, of a Jinja tag starting a line. The attributes of an open
+    /// tag are one level deeper.
+    pub(crate) fn depth(&self) -> usize {
+        if !self.indent {
+            return 0;
+        }
+        self.open.len() + usize::from(matches!(self.state, State::Tag(_)))
+    }
+
+    /// Whether the current position is inside a `<pre>`, `<textarea>`, `<script>` or `<style>`
+    /// element, whose whitespace is kept as it is.
+    pub(crate) fn preformatted(&self) -> bool {
+        self.pre > 0 || matches!(self.state, State::RawText(_))
+    }
+
+    /// The indent level of a text `line` starting at the current position, leaving out the
+    /// elements its leading closing tags close; or `None` if the line is kept as it is, as it is
+    /// without `indent` and in comments and preformatted elements.
+    pub(crate) fn line_depth(&self, line: &str) -> Option<usize> {
+        if !self.indent {
+            return None;
+        }
+        if self.pre > 0 || !matches!(self.state, State::Text) {
+            return matches!(self.state, State::Tag(_)).then(|| self.depth());
+        }
+        let mut depth = self.open.len();
+        let mut rest = line.trim_start();
+        while let Some(after) = rest.strip_prefix("</") {
+            let Some(end) = after.find('>') else {
+                break;
+            };
+            let name = after[..end].trim().to_ascii_lowercase();
+            if let Some(open) = self.open[..depth].iter().rposition(|open| *open == name) {
+                depth = open;
+            }
+            rest = after[end + 1..].trim_start();
+        }
+        Some(depth)
+    }
+
+    /// Keeps the open elements of a Jinja block in step with its branches and returns the
+    /// indent level of the block tag. `blocks` is the number of open Jinja blocks after the tag.
+    /// Every branch starts with the elements open at the start of the block, and the block
+    /// leaves the ones open at the end of its first branch, so that
+    /// `{% if a %}<div class="a">{% else %}<div>{% endif %}` opens a single `<div>`.
+    pub(crate) fn block_tag(&mut self, blocks: usize, branch: bool) -> usize {
+        let depth = self.block_depth(blocks, branch);
+        if self.indent {
+            depth
+        } else {
+            0
+        }
+    }
+
+    fn block_depth(&mut self, blocks: usize, branch: bool) -> usize {
+        if blocks > self.blocks.len() {
+            let depth = self.open.len();
+            self.blocks.push((self.open.clone(), None));
+            return depth;
+        }
+        if blocks < self.blocks.len() {
+            let (start, first_branch) = self.blocks.pop().unwrap();
+            if let Some(first_branch) = first_branch {
+                self.open = first_branch;
+            }
+            return start.len();
+        }
+        match self.blocks.last_mut() {
+            Some((start, first_branch)) if branch => {
+                let open = std::mem::replace(&mut self.open, start.clone());
+                first_branch.get_or_insert(open);
+                start.len()
+            }
+            _ => self.open.len(),
+        }
+    }
+
+    /// Moves past `text`, opening and closing elements for its tags.
+    pub(crate) fn scan(&mut self, text: &str) {
+        let mut i = 0;
+        while let Some(c) = text[i..].chars().next() {
+            let rest = &text[i..];
+            i += c.len_utf8();
+            match &mut self.state {
+                State::Text if rest.starts_with("<!--") => {
+                    self.state = State::Comment;
+                    i += 3;
+                }
+                State::Text if c == '<' => {
+                    let closing = rest[1..].starts_with('/');
+                    let name = &rest[1 + usize::from(closing)..];
+                    if name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '!') {
+                        self.state = State::Tag(Tag {
+                            closing,
+                            ..Tag::default()
+                        });
+                        i += usize::from(closing);
+                    }
+                }
+                State::Text => {}
+                State::Comment => {
+                    if rest.starts_with("-->") {
+                        self.state = State::Text;
+                        i += 2;
+                    }
+                }
+                State::RawText(name) => {
+                    let closes = rest.strip_prefix("</").is_some_and(|after| {
+                        after.len() >= name.len()
+                            && after.as_bytes()[..name.len()].eq_ignore_ascii_case(name.as_bytes())
+                    });
+                    if closes {
+                        self.state = State::Tag(Tag {
+                            closing: true,
+                            ..Tag::default()
+                        });
+                        i += 1;
+                    }
+                }
+                State::Tag(tag) => match tag.quote {
+                    Some(quote) => {
+                        if c == quote {
+                            tag.quote = None;
+                        }
+                    }
+                    None if c == '>' => {
+                        let State::Tag(tag) = std::mem::take(&mut self.state) else {
+                            unreachable!()
+                        };
+                        self.end_tag(tag);
+                    }
+                    None if !tag.name_done && (c.is_alphanumeric() || "-:!".contains(c)) => {
+                        tag.name.push(c.to_ascii_lowercase());
+                    }
+                    None => {
+                        tag.name_done = true;
+                        if c == '"' || c == '\'' {
+                            tag.quote = Some(c);
+                        }
+                        if !c.is_whitespace() {
+                            tag.self_closing = c == '/';
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    fn end_tag(&mut self, tag: Tag) {
+        let name = tag.name.as_str();
+        if tag.closing && name == "pre" {
+            self.pre = self.pre.saturating_sub(1);
+        } else if tag.closing {
+            if let Some(open) = self.open.iter().rposition(|open| open == name) {
+                self.open.truncate(open);
+            }
+        } else if name == "pre" && !tag.self_closing {
+            self.pre += 1;
+        } else if RAW_TEXT_ELEMENTS.contains(&name) && !tag.self_closing {
+            self.state = State::RawText(tag.name);
+        } else if BLOCK_ELEMENTS.contains(&name) && !tag.self_closing {
+            if IMPLICITLY_CLOSED.contains(&name) && self.open.last() == Some(&tag.name) {
+                self.open.pop();
+            }
+            self.open.push(tag.name);
+        }
+    }
+}
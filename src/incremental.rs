@@ -0,0 +1,66 @@
+use tree_sitter::{InputEdit, Point, Tree};
+
+/// A template parsed by [`crate::Formatter::parse_template`] and kept to be parsed again after
+/// each edit with [`crate::Formatter::reparse`], which reuses the parts of the tree the edit
+/// didn't touch. Only the parse is incremental: [`crate::Formatter::format_parsed`] skips it but
+/// still formats the whole template.
+#[derive(Debug, Clone)]
+pub struct ParsedTemplate {
+    /// The template as it was given and edited.
+    pub(crate) source: String,
+    /// `source` as formatting sees it, without a byte order mark and with `\n` line endings.
+    pub(crate) input: String,
+    /// `input` with the bodies of `{% raw %}` blocks blanked out, which is what was parsed.
+    pub(crate) masked: String,
+    pub(crate) tree: Tree,
+}
+
+impl ParsedTemplate {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The tree of the template with `\n` line endings and without a byte order mark, as
+    /// [`crate::Formatter::parse`] would give it.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+}
+
+/// The edit turning `old` into `new`: the bytes between the prefix and the suffix they share.
+/// Comparing the parsed texts rather than taking the caller's edit also catches the bytes a
+/// new `{% raw %}` tag blanks out further on.
+pub(crate) fn input_edit(old: &str, new: &str) -> InputEdit {
+    let (old_bytes, new_bytes) = (old.as_bytes(), new.as_bytes());
+    let prefix = common_len(old_bytes.iter(), new_bytes.iter());
+    let suffix = common_len(
+        old_bytes[prefix..].iter().rev(),
+        new_bytes[prefix..].iter().rev(),
+    );
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+    InputEdit {
+        start_byte: prefix,
+        old_end_byte: old_end,
+        new_end_byte: new_end,
+        start_position: point(old_bytes, prefix),
+        old_end_position: point(old_bytes, old_end),
+        new_end_position: point(new_bytes, new_end),
+    }
+}
+
+fn common_len<'a>(a: impl Iterator<Item = &'a u8>, b: impl Iterator<Item = &'a u8>) -> usize {
+    a.zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// The row and byte column of `offset` in `text`, both 0-based.
+fn point(text: &[u8], offset: usize) -> Point {
+    let before = &text[..offset];
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    Point {
+        row: before.iter().filter(|&&b| b == b'\n').count(),
+        column: offset - line_start,
+    }
+}
@@ -0,0 +1,76 @@
+//! A small tokenizer for the text inside `{{ }}` and `{% %}`. The grammar's own string rule
+//! can't hold the other quote character, so string literals are recognized here instead.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TokenKind {
+    /// A quoted string literal, quotes included. Unterminated strings run to the end.
+    String,
+    /// `(`, `[` or `{`
+    Open,
+    /// `)`, `]` or `}`
+    Close,
+    Comma,
+    Pipe,
+    Whitespace,
+    /// Anything else: names, numbers, operators.
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Token<'a> {
+    pub(crate) kind: TokenKind,
+    pub(crate) text: &'a str,
+    /// Number of brackets open around the token. Brackets count themselves as inside.
+    pub(crate) depth: usize,
+}
+
+fn string_end(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if c == quote => return i + c.len_utf8(),
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+pub(crate) fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut depth = 0usize;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (kind, len) = match c {
+            '\'' | '"' => (TokenKind::String, string_end(rest, c)),
+            '(' | '[' | '{' => (TokenKind::Open, 1),
+            ')' | ']' | '}' => (TokenKind::Close, 1),
+            ',' => (TokenKind::Comma, 1),
+            '|' => (TokenKind::Pipe, 1),
+            _ if c.is_whitespace() => (
+                TokenKind::Whitespace,
+                rest.find(|c: char| !c.is_whitespace())
+                    .unwrap_or(rest.len()),
+            ),
+            _ => (
+                TokenKind::Other,
+                rest.find(|c: char| c.is_whitespace() || "'\"()[]{},|".contains(c))
+                    .unwrap_or(rest.len()),
+            ),
+        };
+        if kind == TokenKind::Open {
+            depth += 1;
+        }
+        tokens.push(Token {
+            kind,
+            text: &rest[..len],
+            depth,
+        });
+        if kind == TokenKind::Close {
+            depth = depth.saturating_sub(1);
+        }
+        rest = &rest[len..];
+    }
+    tokens
+}
@@ -0,0 +1,90 @@
+mod align;
+#[cfg(feature = "config")]
+pub mod config;
+mod diagnostics;
+mod diff;
+mod error;
+mod formatter;
+mod html;
+mod incremental;
+mod lexer;
+#[cfg(feature = "node")]
+mod node;
+mod normalize;
+mod options;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod wrap;
+
+pub use diagnostics::{collect_syntax_errors, SyntaxError};
+pub use diff::unified_diff;
+pub use error::FormatError;
+pub use formatter::{
+    new_parser, peek_jinja_stmt_keyword, FormatReport, Formatter, FormatterBuilder,
+};
+pub use incremental::ParsedTemplate;
+pub use options::{
+    FormatOptions, IndentStyle, LineEnding, PipeSpacing, QuoteStyle, TrailingComma, TrailingNewline,
+};
+
+/// Formats `input` with `options`. This and [`format_string_report`] are the entry points of the
+/// bindings, the HTTP handlers and the CLI; [`Formatter`] also formats line ranges, streams the
+/// output and takes parsers of the caller's.
+///
+/// ```
+/// use jinja_formatter::{format_string, FormatOptions};
+///
+/// let formatted = format_string("{% if x %}{{x}}{% endif %}", &FormatOptions::default())?;
+/// assert_eq!(formatted, "{% if x %}\n  {{ x }}\n{% endif %}\n");
+///
+/// let options = FormatOptions {
+///     indent_size: 4,
+///     ..FormatOptions::default()
+/// };
+/// let formatted = format_string("{% for x in xs %}{{ x }}{% endfor %}", &options)?;
+/// assert_eq!(formatted, "{% for x in xs %}\n    {{ x }}\n{% endfor %}\n");
+/// # Ok::<(), jinja_formatter::FormatError>(())
+/// ```
+///
+/// Templates that don't parse or whose blocks don't match fail with the reason:
+///
+/// ```
+/// use jinja_formatter::{format_string, FormatError, FormatOptions};
+///
+/// let err = format_string("{% if x %}{% endfor %}", &FormatOptions::default()).unwrap_err();
+/// assert!(matches!(err, FormatError::UnbalancedBlock { line: 1, column: 11, .. }));
+/// assert_eq!(
+///     err.to_string(),
+///     "Unbalanced block: expected `endif` but found `endfor` at line 1, column 11"
+/// );
+/// ```
+pub fn format_string(input: &str, options: &FormatOptions) -> Result<String, FormatError> {
+    Formatter::borrowed(options).format(input)
+}
+
+/// Like [`format_string`], but describes the result: whether it changed, the warnings and the
+/// errors best-effort formatting got past.
+///
+/// ```
+/// use jinja_formatter::{format_string_report, FormatOptions};
+///
+/// let report = format_string_report("{{x}}", &FormatOptions::default())?;
+/// assert!(report.changed);
+/// assert_eq!(report.output, "{{ x }}\n");
+/// # Ok::<(), jinja_formatter::FormatError>(())
+/// ```
+pub fn format_string_report(
+    input: &str,
+    options: &FormatOptions,
+) -> Result<FormatReport, FormatError> {
+    Formatter::borrowed(options).format_report(input)
+}
+
+/// [`format_string`] with the default options.
+pub fn format_template(input: &str) -> Result<String, FormatError> {
+    format_string(input, &FormatOptions::default())
+}
@@ -1,167 +1,139 @@
-use std::{convert::Infallible, future::IntoFuture, net::SocketAddr};
+use std::{future::IntoFuture, net::SocketAddr, process::ExitCode};
 
-use axum::{
-    extract::Request,
-    http::StatusCode,
-    response::Response,
-    routing::{get, post},
-    Router, ServiceExt,
-};
-use lazy_static::lazy_static;
-use pulldown_cmark::html;
+use axum::{extract::Request, ServiceExt};
+use clap::Parser;
+use jinja_formatter::server;
 use tokio::net::TcpListener;
-
-const INDENT_SIZE: usize = 2;
-
-lazy_static! {
-    static ref INDEX_HTML_BODY: String = render_md_to_html(include_str!("../README.md"));
-    static ref INDEX_HTML: String = format!(
-        r#"<!DOCTYPE html>
-<html>
-<head>
-    <title>Home</title>
-    <link rel="stylesheet" type="text/css" href="https://cdnjs.cloudflare.com/ajax/libs/normalize/8.0.0/normalize.min.css" />
-    <style>
-    blockquote {{
-        display: none;
-    }}
-    </style>
-</head>
-<body>
-    <div style="max-width: 800px; margin: 0 auto; padding: 20px;">
-        {}
-        <div>Index generated from README.md</div>
-    </div>
-</body>
-</html>"#,
-        *INDEX_HTML_BODY
-    );
+use tracing_subscriber::EnvFilter;
+
+/// Serve the formatter over HTTP.
+#[derive(Parser)]
+#[command(name = "jinja-formatter", version)]
+struct Args {
+    /// Address to listen on
+    #[arg(long, env = "JINJA_FMT_ADDR", default_value = "0.0.0.0:18018")]
+    addr: SocketAddr,
+    /// Largest accepted request body in bytes
+    #[arg(long, env = "JINJA_FMT_BODY_LIMIT", default_value_t = server::DEFAULT_BODY_LIMIT)]
+    body_limit: usize,
+    /// Give up on templates that take longer to parse, `0` for no limit
+    #[arg(
+        long,
+        env = "JINJA_FMT_PARSE_TIMEOUT_MICROS",
+        default_value_t = server::DEFAULT_PARSE_TIMEOUT_MICROS
+    )]
+    parse_timeout_micros: u64,
+    /// Require `Authorization: Bearer <token>` on the formatting endpoints
+    #[arg(long, env = "JINJA_FMT_AUTH_TOKEN", hide_env_values = true)]
+    auth_token: Option<String>,
+    /// Comma-separated origins browsers may call the server from, `*` for any
+    #[arg(
+        long,
+        env = "JINJA_FMT_CORS_ORIGINS",
+        value_delimiter = ',',
+        default_value = "*"
+    )]
+    cors_origins: Vec<String>,
+    /// Formatting requests each client IP may make per minute, `0` for no limit
+    #[arg(long, env = "JINJA_FMT_RATE_LIMIT", default_value_t = 0)]
+    rate_limit_per_minute: u32,
+    /// Number of `/format` results to keep for repeated requests, `0` for none
+    #[arg(long, env = "JINJA_FMT_CACHE_SIZE", default_value_t = 0)]
+    cache_size: usize,
+    /// Threads serving requests, by default one per CPU
+    #[arg(
+        long,
+        env = "JINJA_FMT_WORKER_THREADS",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    worker_threads: Option<u16>,
 }
 
-fn render_md_to_html(md: &str) -> String {
-    let mut options = pulldown_cmark::Options::empty();
-    options.insert(pulldown_cmark::Options::ENABLE_STRIKETHROUGH);
-    let parser = pulldown_cmark::Parser::new_ext(md, options);
-    let mut html_output = String::new();
-    html::push_html(&mut html_output, parser);
-    html_output
-}
-
-fn peek_jinja_stmt_keyword(node: tree_sitter::Node, source: &[u8]) -> Option<String> {
-    let kind = node.kind();
-    if kind != "statement" {
-        return None;
-    }
-    if let Some(keyword_node) = node.child(1) {
-        if let Ok(text) = keyword_node.utf8_text(source) {
-            return match text {
-                "if" | "elif" | "else" | "endif" | "for" | "endfor" | "macro" | "endmacro"
-                | "call" | "endcall" | "filter" | "endfilter" => {
-                    return Some(text.to_string());
-                }
-                _ => None,
-            };
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("failed to listen for Ctrl-C: {}", err);
+            std::future::pending::<()>().await;
         }
-    }
-    None
-}
-
-fn format_jinja_node(root_node: tree_sitter::Node, source: &[u8]) -> String {
-    let mut formatted = "".to_string();
-    // dfs
-    let mut curr_ident = 0;
-    let mut next_ident = 0;
-    let mut last_node_kind = "";
-
-    for i in 0..root_node.child_count() {
-        let node = root_node.child(i).unwrap();
-        let keyword = peek_jinja_stmt_keyword(node, source);
-        if let Some(keyword) = keyword {
-            match keyword.as_str() {
-                "if" | "for" | "macro" | "call" | "filter" => {
-                    next_ident += 1;
-                }
-                "elif" | "else" => {
-                    curr_ident -= 1;
-                }
-                "endif" | "endfor" | "endmacro" | "endcall" | "endfilter" => {
-                    curr_ident -= 1;
-                    next_ident -= 1;
-                }
-                _ => {
-                    panic!("unknown keyword: {}", keyword);
-                }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
             }
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-        if node.kind() != "expression" || last_node_kind != "expression" {
-            formatted.push('\n');
-            formatted.push_str(&" ".repeat(curr_ident * INDENT_SIZE));
-        }
-
-        let raw_text = node.utf8_text(source).unwrap();
-        formatted.push_str(raw_text);
-
-        last_node_kind = node.kind();
-        curr_ident = next_ident;
-    }
-    formatted[1..].to_string() + "\n"
-}
-
-#[derive(serde::Deserialize)]
-struct FormatRequestBody {
-    input: String,
-}
-
-async fn format_jinja(body: String) -> Result<Response, Infallible> {
-    let input = serde_json::from_str::<FormatRequestBody>(&body);
-    if input.is_err() {
-        return Ok(Response::builder()
-            .status(StatusCode::BAD_REQUEST)
-            .header("Content-Type", "text/plain")
-            .body("Invalid request body JSON".to_string().into())
-            .unwrap());
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
-    let input = input.unwrap().input;
-
-    let jinja_template = input.as_str();
-    let mut parser = tree_sitter::Parser::new();
-    parser
-        .set_language(&tree_sitter_jinja2::language())
-        .expect("Error loading jinja2 grammar");
-    let tree = parser
-        .parse(jinja_template, None)
-        .expect("Failed to parse code");
-
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "text/plain")
-        .body(format_jinja_node(tree.root_node(), jinja_template.as_bytes()).into())
-        .unwrap())
+    tracing::info!("shutting down, waiting for in-flight requests");
 }
 
-async fn index() -> Response {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "text/html")
-        .body(INDEX_HTML.clone().into())
-        .unwrap()
+fn main() -> ExitCode {
+    let args = Args::parse();
+    // `RUST_LOG` picks the levels, e.g. `RUST_LOG=jinja_formatter=debug,tower_http=debug`
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+    let worker_threads = args.worker_threads.map_or_else(
+        || std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+        usize::from,
+    );
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads)
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            tracing::error!("failed to start the runtime: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    tracing::info!(worker_threads, "starting the runtime");
+    runtime.block_on(serve(args))
 }
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
-async fn main() {
-    let router = Router::new()
-        .route("/format", post(format_jinja))
-        .route("/", get(index));
-
-    let listener = TcpListener::bind("0.0.0.0:18018").await.unwrap();
-    println!("Listening on http://0.0.0.0:18018");
+async fn serve(args: Args) -> ExitCode {
+    let router = server::router_with_config(&server::ServerConfig {
+        body_limit: args.body_limit,
+        parse_timeout_micros: args.parse_timeout_micros,
+        auth_token: args.auth_token.filter(|token| !token.is_empty()),
+        cors_origins: args.cors_origins,
+        rate_limit_per_minute: args.rate_limit_per_minute,
+        cache_size: args.cache_size,
+    });
+
+    let listener = match TcpListener::bind(args.addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!("failed to listen on {}: {}", args.addr, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    tracing::info!("listening on http://{}", args.addr);
+    tokio::spawn(server::log_latency_percentiles());
 
     axum::serve(
         listener,
         ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(router),
     )
+    .with_graceful_shutdown(shutdown_signal())
     .into_future()
     .await
     .unwrap();
+    tracing::info!("shutdown complete");
+    ExitCode::SUCCESS
 }
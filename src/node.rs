@@ -0,0 +1,21 @@
+//! The Node.js addon, built with napi-rs.
+
+use napi::{Error, Result};
+use napi_derive::napi;
+
+use crate::{format_string, FormatOptions};
+
+/// Formats `input`. `options` takes the same keys as `.jinjafmt.toml`, e.g.
+/// `{ indent_size: 4, max_line_width: 100 }`. Throws with the reason if the options are invalid
+/// or the template can't be formatted.
+#[napi]
+// napi registers the export only outside of test builds
+#[cfg_attr(test, allow(dead_code))]
+pub fn format(input: String, options: Option<serde_json::Value>) -> Result<String> {
+    let options: FormatOptions = match options {
+        Some(options) => serde_json::from_value(options)
+            .map_err(|err| Error::from_reason(format!("Invalid options: {}", err)))?,
+        None => FormatOptions::default(),
+    };
+    format_string(&input, &options).map_err(|err| Error::from_reason(err.to_string()))
+}
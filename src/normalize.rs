@@ -0,0 +1,205 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::{
+    lexer::{tokenize, TokenKind},
+    QuoteStyle,
+};
+
+/// A `{% ... %}`, `{{ ... }}` or `{# ... #}` tag split into its delimiters, whitespace-control
+/// markers and the untouched text between them.
+pub(crate) struct TagParts<'a> {
+    pub(crate) open: &'a str,
+    pub(crate) open_marker: Option<char>,
+    pub(crate) inner: &'a str,
+    pub(crate) close_marker: Option<char>,
+    pub(crate) close: &'a str,
+}
+
+pub(crate) fn split_tag(text: &str) -> Option<TagParts<'_>> {
+    let (open, close, markers): (_, _, &[char]) = if text.starts_with("{%") {
+        ("{%", "%}", &['-', '+'])
+    } else if text.starts_with("{{") {
+        ("{{", "}}", &['-'])
+    } else if text.starts_with("{#") {
+        ("{#", "#}", &['-'])
+    } else {
+        return None;
+    };
+    let inner = text[open.len()..].strip_suffix(close)?;
+
+    let open_marker = inner.chars().next().filter(|c| markers.contains(c));
+    let inner = &inner[open_marker.map_or(0, char::len_utf8)..];
+    let close_marker = inner.chars().last().filter(|c| markers.contains(c));
+    let inner = &inner[..inner.len() - close_marker.map_or(0, char::len_utf8)];
+
+    Some(TagParts {
+        open,
+        open_marker,
+        inner,
+        close_marker,
+        close,
+    })
+}
+
+/// Puts exactly one space after the opening delimiter and before the closing one, on the inside
+/// of any whitespace-control marker, e.g. `{%-if x%}` becomes `{%- if x %}` and `{{foo  -}}`
+/// becomes `{{ foo -}}`. The markers and the text between them are left as they are, and so is
+/// a line break right inside the delimiters of a comment.
+pub(crate) fn normalize_tag_spacing(text: &str) -> String {
+    let Some(parts) = split_tag(text) else {
+        return text.to_string();
+    };
+    if parts.inner.trim().is_empty() {
+        return text.to_string();
+    }
+    let comment = parts.open == "{#";
+    let (start, inner) = match parts.inner.trim_start() {
+        trimmed if comment && parts.inner[..parts.inner.len() - trimmed.len()].contains('\n') => {
+            (&parts.inner[..parts.inner.len() - trimmed.len()], trimmed)
+        }
+        trimmed => (" ", trimmed),
+    };
+    let (inner, end) = match inner.trim_end() {
+        trimmed if comment && inner[trimmed.len()..].contains('\n') => {
+            (trimmed, &inner[trimmed.len()..])
+        }
+        trimmed => (trimmed, " "),
+    };
+
+    let mut normalized = parts.open.to_string();
+    normalized.extend(parts.open_marker);
+    normalized.push_str(start);
+    normalized.push_str(inner);
+    normalized.push_str(end);
+    normalized.extend(parts.close_marker);
+    normalized.push_str(parts.close);
+    normalized
+}
+
+/// How [`normalize_operator_spacing`] pads an operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Padding {
+    /// `a|b`
+    Tight,
+    /// `a | b`
+    Spaced,
+    /// `a, b`, or `a,)` before a closing bracket. A line break after the operator is kept.
+    Trailing,
+    /// The operator is dropped along with the whitespace around it.
+    Removed,
+    /// A `,` takes the place of the whitespace before the empty range.
+    Added,
+}
+
+/// Rewrites the whitespace around the operators at the sorted, non-overlapping byte ranges
+/// `operators` of `text` as their `Padding` says.
+pub(crate) fn normalize_operator_spacing<'a>(
+    text: &'a str,
+    operators: &[(Range<usize>, Padding)],
+) -> Cow<'a, str> {
+    if operators.is_empty() {
+        return Cow::Borrowed(text);
+    }
+    let mut normalized = String::with_capacity(text.len() + operators.len() * 2);
+    let mut rest_start = 0;
+    for (operator, padding) in operators {
+        let after = &text[operator.end..];
+        let whitespace_after = &after[..after.len() - after.trim_start().len()];
+        let (before, after) = match padding {
+            Padding::Tight => ("", ""),
+            Padding::Spaced => (" ", " "),
+            Padding::Trailing if whitespace_after.contains('\n') => ("", whitespace_after),
+            Padding::Trailing if after.trim_start().starts_with([')', ']', '}']) => ("", ""),
+            Padding::Trailing => ("", " "),
+            Padding::Removed | Padding::Added => ("", ""),
+        };
+        normalized.push_str(text[rest_start..operator.start].trim_end());
+        normalized.push_str(before);
+        match padding {
+            Padding::Removed => {}
+            Padding::Added => normalized.push(','),
+            _ => normalized.push_str(&text[operator.clone()]),
+        }
+        normalized.push_str(after);
+        rest_start = operator.end + whitespace_after.len();
+    }
+    normalized.push_str(&text[rest_start..]);
+    Cow::Owned(normalized)
+}
+
+/// Strips the spaces and tabs ending each line of `text` but the last.
+pub(crate) fn trim_line_ends(text: &str) -> String {
+    let mut trimmed = String::new();
+    let mut lines = text.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        if lines.peek().is_none() {
+            trimmed.push_str(line);
+            break;
+        }
+        trimmed.push_str(line.trim_end_matches([' ', '\t']));
+        trimmed.push('\n');
+    }
+    trimmed
+}
+
+/// Strips the spaces and tabs ending each line of a tag spanning several lines, except inside
+/// string literals where they are part of the value.
+pub(crate) fn trim_tag_line_ends(text: &str) -> String {
+    if !text.contains('\n') || text.starts_with("{#") {
+        return trim_line_ends(text);
+    }
+    tokenize(text)
+        .iter()
+        .map(|token| match token.kind {
+            TokenKind::Whitespace => trim_line_ends(token.text),
+            _ => token.text.to_string(),
+        })
+        .collect()
+}
+
+/// Rewrites the string literals in a `{{ }}` or `{% %}` tag to the quotes of `style`, dropping
+/// the escapes the old quotes needed. Literals holding the new quote keep theirs, the grammar
+/// can't parse an escaped quote.
+pub(crate) fn normalize_quotes(text: &str, style: QuoteStyle) -> Cow<'_, str> {
+    let quote = match style {
+        QuoteStyle::Preserve => return Cow::Borrowed(text),
+        QuoteStyle::Single => '\'',
+        QuoteStyle::Double => '"',
+    };
+    tokenize(text)
+        .iter()
+        .map(|token| match token.kind {
+            TokenKind::String => requote(token.text, quote),
+            _ => token.text.to_string(),
+        })
+        .collect()
+}
+
+/// `literal` in `quote`s, or as it is if it is unterminated or holds a `quote`.
+fn requote(literal: &str, quote: char) -> String {
+    let old = literal.chars().next().unwrap();
+    if old == quote || literal[1..].contains(quote) {
+        return literal.to_string();
+    }
+    let mut requoted = quote.to_string();
+    let mut chars = literal[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped == old => requoted.push(old),
+                Some(escaped) => {
+                    requoted.push('\\');
+                    requoted.push(escaped);
+                }
+                None => break,
+            },
+            _ if c == old && chars.as_str().is_empty() => {
+                requoted.push(quote);
+                return requoted;
+            }
+            _ => requoted.push(c),
+        }
+    }
+    literal.to_string()
+}
@@ -0,0 +1,153 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndentStyle {
+    #[default]
+    Spaces,
+    Tabs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingNewline {
+    /// Always end with a single `\n`.
+    #[default]
+    Insert,
+    /// Never end with a `\n`.
+    Remove,
+    /// End with as many `\n` as the input does, none or several.
+    Preserve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// Whichever of `\n` and `\r\n` ends most lines of the input.
+    #[default]
+    Auto,
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PipeSpacing {
+    /// `name | upper`
+    #[default]
+    Spaces,
+    /// `name|upper`
+    Tight,
+    /// Leave the spacing around `|` as it is.
+    Preserve,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteStyle {
+    /// Leave string literals quoted as they are.
+    #[default]
+    Preserve,
+    /// `'foo'`
+    Single,
+    /// `"foo"`
+    Double,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingComma {
+    /// Leave the trailing commas of inline literals as they are. Expanded literals get one.
+    #[default]
+    Preserve,
+    /// Every literal ends with a comma, inline and expanded ones alike.
+    Always,
+    /// No literal ends with a comma, expanded ones neither.
+    Never,
+}
+
+/// How to format a template. Missing fields keep their [`Default`] when deserialized, which are
+/// the keys of `.jinjafmt.toml` and of `/format` requests.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatOptions {
+    /// Number of spaces per indent level. Ignored for `IndentStyle::Tabs`.
+    pub indent_size: usize,
+    /// One `\t` per indent level for `IndentStyle::Tabs`.
+    pub indent_style: IndentStyle,
+    /// Whether the output ends with a `\n`. Extra trailing newlines are dropped, except with
+    /// `TrailingNewline::Preserve`.
+    pub trailing_newline: TrailingNewline,
+    /// Line ending used for every line of the output.
+    pub line_ending: LineEnding,
+    /// Maximum number of consecutive blank lines kept in the output.
+    pub max_blank_lines: usize,
+    /// Spacing around the filter pipes of expressions and `{% filter %}` tags.
+    pub pipe_spacing: PipeSpacing,
+    /// Put one space after the commas of argument lists and literals and none before them. A
+    /// line break after a comma is kept.
+    pub comma_spacing: bool,
+    /// Put one space on each side of the binary operators of expressions and statements, like
+    /// `==`, `+` and `and`. Signs such as the `-` of `-1` are left alone.
+    pub operator_spacing: bool,
+    /// Put spaces around the `=` of the parameter defaults of `{% macro %}` and `{% call %}`
+    /// signatures and of the keyword arguments of the macro a `{% call %}` calls, as in `b = 1`.
+    /// By default they are written `b=1`.
+    pub macro_default_spacing: bool,
+    /// Quotes of the string literals in expressions and statements.
+    pub quote_style: QuoteStyle,
+    /// Tags that start a line longer than this many characters are wrapped at filter pipes or
+    /// argument commas. `0` never wraps.
+    pub max_line_width: usize,
+    /// With a `max_line_width`, tags that are too long and hold a dict or list literal get one
+    /// entry of it per line instead of being wrapped.
+    pub expand_literals: bool,
+    /// Trailing commas of dict, list and tuple literals. Those of single-item tuples such as
+    /// `(a,)` and of literals spanning several lines are always kept.
+    pub trailing_comma: TrailingComma,
+    /// Also indent by the nesting of block-level HTML elements such as `<div>` and `<li>`, and
+    /// reindent the lines of text to match.
+    pub indent_html: bool,
+    /// Pad the targets of `{% set %}` assignments on consecutive lines so that their `=` line
+    /// up. A blank line or any other tag ends the run.
+    pub align_set_assignments: bool,
+    /// Strip spaces and tabs from the end of every line, except inside `{% raw %}` blocks and
+    /// `fmt: off` regions.
+    pub trim_trailing_whitespace: bool,
+    /// Start the output with a byte order mark if the input starts with one. By default it is
+    /// dropped.
+    pub preserve_bom: bool,
+    /// Format templates with syntax errors too, copying the tags holding the errors as they
+    /// are. They are listed in `FormatReport::errors`.
+    pub best_effort: bool,
+    /// Give up parsing after this many microseconds, `0` for no limit.
+    pub parse_timeout_micros: u64,
+    /// Extra `(open, close)` tag pairs, such as `("cache", "endcache")`, indented like the
+    /// built-in blocks.
+    pub custom_blocks: Vec<(String, String)>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent_size: 2,
+            indent_style: IndentStyle::Spaces,
+            trailing_newline: TrailingNewline::Insert,
+            line_ending: LineEnding::Auto,
+            max_blank_lines: 1,
+            pipe_spacing: PipeSpacing::Spaces,
+            comma_spacing: true,
+            operator_spacing: false,
+            macro_default_spacing: false,
+            quote_style: QuoteStyle::Preserve,
+            max_line_width: 0,
+            expand_literals: false,
+            trailing_comma: TrailingComma::Preserve,
+            indent_html: false,
+            align_set_assignments: false,
+            trim_trailing_whitespace: true,
+            preserve_bom: false,
+            best_effort: false,
+            parse_timeout_micros: 0,
+            custom_blocks: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! The `jinja_formatter` Python module. `pyproject.toml` builds it with maturin.
+
+use pyo3::{create_exception, exceptions::PyException, prelude::*};
+
+use crate::{format_string, FormatOptions};
+
+create_exception!(
+    jinja_formatter,
+    FormatError,
+    PyException,
+    "The template couldn't be formatted."
+);
+
+/// Formats `input` with `indent_size` spaces per level. Raises `FormatError` with the reason if
+/// the template can't be formatted.
+#[pyfunction]
+#[pyo3(signature = (input, indent_size = 2))]
+fn format(input: &str, indent_size: usize) -> PyResult<String> {
+    let options = FormatOptions {
+        indent_size,
+        ..FormatOptions::default()
+    };
+    format_string(input, &options).map_err(|err| FormatError::new_err(err.to_string()))
+}
+
+#[pymodule]
+fn jinja_formatter(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(format, module)?)?;
+    module.add("FormatError", module.py().get_type::<FormatError>())?;
+    Ok(())
+}
@@ -0,0 +1,1214 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    hash::{BuildHasher, RandomState},
+    io::{Cursor, Read, Write},
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, DefaultBodyLimit, Extension, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use lazy_static::lazy_static;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use pulldown_cmark::html;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    decompression::RequestDecompressionLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+    LatencyUnit,
+};
+use tracing::Level;
+use zip::{result::ZipError, write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{
+    diff::changed_lines, format_string, format_string_report, unified_diff, FormatError,
+    FormatOptions, FormatReport, Formatter, IndentStyle, LineEnding, PipeSpacing, QuoteStyle,
+    TrailingComma, TrailingNewline,
+};
+
+lazy_static! {
+    static ref METRICS: PrometheusHandle = install_metrics_recorder();
+    static ref LATENCIES: LatencyWindow = LatencyWindow::new();
+    static ref INDEX_HTML_BODY: String = render_md_to_html(include_str!("../README.md"));
+    static ref INDEX_HTML: String = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Home</title>
+    <link rel="stylesheet" type="text/css" href="https://cdnjs.cloudflare.com/ajax/libs/normalize/8.0.0/normalize.min.css" />
+    <style>
+    blockquote {{
+        display: none;
+    }}
+    </style>
+</head>
+<body>
+    <div style="max-width: 800px; margin: 0 auto; padding: 20px;">
+        {}
+        <div>Index generated from README.md</div>
+    </div>
+</body>
+</html>"#,
+        *INDEX_HTML_BODY
+    );
+}
+
+fn render_md_to_html(md: &str) -> String {
+    let mut options = pulldown_cmark::Options::empty();
+    options.insert(pulldown_cmark::Options::ENABLE_STRIKETHROUGH);
+    let parser = pulldown_cmark::Parser::new_ext(md, options);
+    let mut html_output = String::new();
+    html::push_html(&mut html_output, parser);
+    html_output
+}
+
+const FORMAT_DURATION: &str = "format_duration_seconds";
+const FORMAT_REQUESTS: &str = "format_requests_total";
+
+fn install_metrics_recorder() -> PrometheusHandle {
+    let recorder = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full(FORMAT_DURATION.to_string()),
+            &[0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0],
+        )
+        .unwrap()
+        .build_recorder();
+    let handle = recorder.handle();
+    // an embedding application may have installed its own recorder already
+    let _ = metrics::set_global_recorder(recorder);
+    handle
+}
+
+/// How far back the percentiles of `/stats` look.
+pub const LATENCY_WINDOW: Duration = Duration::from_secs(60);
+/// Format calls kept for `/stats`; under heavier load the window holds only the latest ones.
+const LATENCY_SLOTS: usize = 4096;
+
+/// The durations of the latest format calls, for their percentiles. Each slot packs the second
+/// a call ended, counted from `start` and `0` for a slot never written, with the microseconds it
+/// took into one atomic, so recording takes no lock.
+struct LatencyWindow {
+    start: Instant,
+    next: AtomicUsize,
+    slots: Vec<AtomicU64>,
+}
+
+impl LatencyWindow {
+    fn new() -> Self {
+        LatencyWindow {
+            start: Instant::now(),
+            next: AtomicUsize::new(0),
+            slots: (0..LATENCY_SLOTS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_secs() + 1
+    }
+
+    fn record(&self, duration: Duration) {
+        let micros = duration.as_micros().min(u32::MAX as u128) as u64;
+        let slot = self.next.fetch_add(1, Ordering::Relaxed) % LATENCY_SLOTS;
+        self.slots[slot].store(self.now() << 32 | micros, Ordering::Relaxed);
+    }
+
+    /// The durations in microseconds of the calls that ended within [`LATENCY_WINDOW`], sorted.
+    fn recent(&self) -> Vec<u64> {
+        let now = self.now();
+        let mut micros: Vec<u64> = self
+            .slots
+            .iter()
+            .map(|slot| slot.load(Ordering::Relaxed))
+            .filter(|&packed| {
+                packed != 0 && now.saturating_sub(packed >> 32) < LATENCY_WINDOW.as_secs()
+            })
+            .map(|packed| packed & u64::from(u32::MAX))
+            .collect();
+        micros.sort_unstable();
+        micros
+    }
+}
+
+/// The nearest-rank percentile `p` of the `sorted` microseconds, in seconds.
+fn percentile(sorted: &[u64], p: f64) -> Option<f64> {
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    let micros = sorted.get(rank.saturating_sub(1))?;
+    Some(*micros as f64 / 1e6)
+}
+
+/// The p50, p95 and p99 format latency over [`LATENCY_WINDOW`], as served by `/stats`.
+fn latency_stats() -> serde_json::Value {
+    let recent = LATENCIES.recent();
+    serde_json::json!({
+        "window_seconds": LATENCY_WINDOW.as_secs(),
+        "count": recent.len(),
+        "latency_seconds": {
+            "p50": percentile(&recent, 0.50),
+            "p95": percentile(&recent, 0.95),
+            "p99": percentile(&recent, 0.99),
+        },
+    })
+}
+
+/// Logs the format latency percentiles once per [`LATENCY_WINDOW`] that had any format calls.
+pub async fn log_latency_percentiles() {
+    let mut interval = tokio::time::interval(LATENCY_WINDOW);
+    // the first tick is immediate, with nothing recorded yet
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let recent = LATENCIES.recent();
+        if let (Some(p50), Some(p95), Some(p99)) = (
+            percentile(&recent, 0.50),
+            percentile(&recent, 0.95),
+            percentile(&recent, 0.99),
+        ) {
+            tracing::info!(
+                count = recent.len(),
+                p50,
+                p95,
+                p99,
+                "format latency in seconds"
+            );
+        }
+    }
+}
+
+fn record_outcome(outcome: &'static str) {
+    metrics::counter!(FORMAT_REQUESTS, "outcome" => outcome).increment(1);
+}
+
+fn error_outcome(err: &FormatError) -> &'static str {
+    match err {
+        FormatError::ParseError(_) => "parse_error",
+        FormatError::UnknownKeyword(_) => "unknown_keyword",
+        FormatError::UnbalancedBlock { .. } => "unbalanced_block",
+        FormatError::Utf8 => "utf8",
+        FormatError::Timeout => "timeout",
+        FormatError::Io(_) => "io",
+    }
+}
+
+/// Runs one format call, recording how long it took and how it ended.
+fn measure_format<T>(format: impl FnOnce() -> Result<T, FormatError>) -> Result<T, FormatError> {
+    let start = Instant::now();
+    let result = format();
+    let elapsed = start.elapsed();
+    metrics::histogram!(FORMAT_DURATION).record(elapsed.as_secs_f64());
+    LATENCIES.record(elapsed);
+    record_outcome(result.as_ref().map_or_else(error_outcome, |_| "ok"));
+    if let Err(err) = &result {
+        tracing::warn!(kind = error_outcome(err), %err, "formatting failed");
+    }
+    result
+}
+
+#[derive(Default, serde::Deserialize)]
+struct RequestOptions {
+    indent_size: Option<usize>,
+    indent_style: Option<IndentStyle>,
+    line_ending: Option<LineEnding>,
+    max_blank_lines: Option<usize>,
+    max_line_width: Option<usize>,
+    expand_literals: Option<bool>,
+    trailing_comma: Option<TrailingComma>,
+    pipe_spacing: Option<PipeSpacing>,
+    comma_spacing: Option<bool>,
+    operator_spacing: Option<bool>,
+    macro_default_spacing: Option<bool>,
+    quote_style: Option<QuoteStyle>,
+    indent_html: Option<bool>,
+    align_set_assignments: Option<bool>,
+    trim_trailing_whitespace: Option<bool>,
+    trailing_newline: Option<TrailingNewline>,
+    preserve_bom: Option<bool>,
+    best_effort: Option<bool>,
+    custom_blocks: Option<Vec<(String, String)>>,
+}
+
+#[derive(Default, serde::Deserialize)]
+struct FormatRequestBody {
+    input: String,
+    #[serde(flatten)]
+    options: RequestOptions,
+    #[serde(default)]
+    check: bool,
+    /// `"text"` or `"json"`. Defaults to the request's `Accept` header, then text.
+    format: Option<String>,
+    /// `"formatted"` or `"diff"`.
+    output: Option<String>,
+    /// Only format these lines.
+    range: Option<LineRange>,
+}
+
+#[derive(serde::Deserialize)]
+struct LineRange {
+    start_line: usize,
+    end_line: usize,
+}
+
+#[derive(serde::Deserialize)]
+struct BatchItem {
+    id: serde_json::Value,
+    input: String,
+    #[serde(flatten)]
+    options: RequestOptions,
+}
+
+const MAX_INDENT_SIZE: usize = 16;
+
+impl RequestOptions {
+    fn options(&self, config: &ServerConfig) -> Result<FormatOptions, String> {
+        let mut options = FormatOptions {
+            parse_timeout_micros: config.parse_timeout_micros,
+            ..FormatOptions::default()
+        };
+        if let Some(indent_size) = self.indent_size {
+            if indent_size > MAX_INDENT_SIZE {
+                return Err(format!("indent_size must be at most {}", MAX_INDENT_SIZE));
+            }
+            options.indent_size = indent_size;
+        }
+        if let Some(indent_style) = self.indent_style {
+            options.indent_style = indent_style;
+        }
+        if let Some(line_ending) = self.line_ending {
+            options.line_ending = line_ending;
+        }
+        if let Some(max_blank_lines) = self.max_blank_lines {
+            options.max_blank_lines = max_blank_lines;
+        }
+        if let Some(max_line_width) = self.max_line_width {
+            options.max_line_width = max_line_width;
+        }
+        if let Some(expand_literals) = self.expand_literals {
+            options.expand_literals = expand_literals;
+        }
+        if let Some(trailing_comma) = self.trailing_comma {
+            options.trailing_comma = trailing_comma;
+        }
+        if let Some(pipe_spacing) = self.pipe_spacing {
+            options.pipe_spacing = pipe_spacing;
+        }
+        if let Some(comma_spacing) = self.comma_spacing {
+            options.comma_spacing = comma_spacing;
+        }
+        if let Some(operator_spacing) = self.operator_spacing {
+            options.operator_spacing = operator_spacing;
+        }
+        if let Some(macro_default_spacing) = self.macro_default_spacing {
+            options.macro_default_spacing = macro_default_spacing;
+        }
+        if let Some(quote_style) = self.quote_style {
+            options.quote_style = quote_style;
+        }
+        if let Some(indent_html) = self.indent_html {
+            options.indent_html = indent_html;
+        }
+        if let Some(align_set_assignments) = self.align_set_assignments {
+            options.align_set_assignments = align_set_assignments;
+        }
+        if let Some(trim_trailing_whitespace) = self.trim_trailing_whitespace {
+            options.trim_trailing_whitespace = trim_trailing_whitespace;
+        }
+        if let Some(trailing_newline) = self.trailing_newline {
+            options.trailing_newline = trailing_newline;
+        }
+        if let Some(preserve_bom) = self.preserve_bom {
+            options.preserve_bom = preserve_bom;
+        }
+        if let Some(best_effort) = self.best_effort {
+            options.best_effort = best_effort;
+        }
+        if let Some(custom_blocks) = &self.custom_blocks {
+            options.custom_blocks = custom_blocks.clone();
+        }
+        Ok(options)
+    }
+}
+
+fn text_response(status: StatusCode, body: String) -> Response {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain")
+        .body(body.into())
+        .unwrap()
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body.to_string().into())
+        .unwrap()
+}
+
+fn error_status(err: &FormatError) -> StatusCode {
+    match err {
+        FormatError::ParseError(_) | FormatError::UnbalancedBlock { .. } | FormatError::Utf8 => {
+            StatusCode::BAD_REQUEST
+        }
+        FormatError::UnknownKeyword(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        FormatError::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+        FormatError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Whether the body is a bare template rather than JSON.
+fn is_plain_text(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/plain"))
+}
+
+async fn format_jinja(
+    State(config): State<Arc<ServerConfig>>,
+    Extension(cache): Extension<Arc<FormatCache>>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, Infallible> {
+    let input = if is_plain_text(&headers) {
+        Ok(FormatRequestBody {
+            input: body,
+            ..FormatRequestBody::default()
+        })
+    } else {
+        serde_json::from_str::<FormatRequestBody>(&body)
+    };
+    if input.is_err() {
+        record_outcome("bad_json");
+        return Ok(text_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid request body JSON".to_string(),
+        ));
+    }
+    let input = input.unwrap();
+
+    let options = match input.options.options(&config) {
+        Ok(options) => options,
+        Err(message) => {
+            record_outcome("bad_options");
+            return Ok(text_response(StatusCode::BAD_REQUEST, message));
+        }
+    };
+    let json = match input.format.as_deref() {
+        Some("json") => true,
+        Some("text") => false,
+        Some(other) => {
+            return Ok(text_response(
+                StatusCode::BAD_REQUEST,
+                format!("format must be \"text\" or \"json\", got \"{}\"", other),
+            ))
+        }
+        None => wants_json(&headers),
+    };
+    let diff = match input.output.as_deref() {
+        None | Some("formatted") => false,
+        Some("diff") => true,
+        Some(other) => {
+            return Ok(text_response(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "output must be \"formatted\" or \"diff\", got \"{}\"",
+                    other
+                ),
+            ))
+        }
+    };
+    if let Some(range) = &input.range {
+        if range.start_line == 0 || range.start_line > range.end_line {
+            return Ok(text_response(
+                StatusCode::BAD_REQUEST,
+                "range must have 1 <= start_line <= end_line".to_string(),
+            ));
+        }
+    }
+    let range = input
+        .range
+        .as_ref()
+        .map(|range| range.start_line..=range.end_line);
+    // formatting may parse for as long as the timeout allows, off the async runtime's threads
+    let respond = move || format_response(&cache, &input, options, range, json, diff);
+    Ok(tokio::task::spawn_blocking(respond)
+        .await
+        .expect("formatting a template panicked"))
+}
+
+/// Formats the template of a validated `/format` request, or takes the report from `cache`,
+/// and renders the response `json` and `diff` ask for.
+fn format_response(
+    cache: &FormatCache,
+    input: &FormatRequestBody,
+    options: FormatOptions,
+    range: Option<RangeInclusive<usize>>,
+    json: bool,
+    diff: bool,
+) -> Response {
+    let cached = cache.get(&input.input, &options, range.as_ref());
+    let hit = cached.is_some();
+    let result = match cached {
+        Some(report) => {
+            record_outcome("cached");
+            Ok(report)
+        }
+        None => {
+            let result = measure_format(|| match &range {
+                Some(range) => {
+                    Formatter::borrowed(&options).format_range_report(&input.input, range.clone())
+                }
+                None => format_string_report(&input.input, &options),
+            });
+            if let Ok(report) = &result {
+                cache.insert(&input.input, &options, range.as_ref(), report.clone());
+            }
+            result
+        }
+    };
+    match result {
+        Ok(report) => {
+            let changed_lines = changed_lines(&input.input, &report.output);
+            let changed = report.changed;
+            let mut response = if input.check {
+                json_response(
+                    StatusCode::OK,
+                    serde_json::json!({ "would_change": report.changed }),
+                )
+            } else if diff {
+                text_response(
+                    StatusCode::OK,
+                    unified_diff("input", &input.input, &report.output),
+                )
+            } else if json {
+                json_response(StatusCode::OK, serde_json::to_value(report).unwrap())
+            } else {
+                text_response(StatusCode::OK, report.output)
+            };
+            let headers = response.headers_mut();
+            headers.insert(
+                "X-Jinja-Changed",
+                HeaderValue::from_static(if changed { "true" } else { "false" }),
+            );
+            headers.insert("X-Jinja-Lines-Changed", HeaderValue::from(changed_lines));
+            if cache.capacity > 0 {
+                headers.insert(
+                    "X-Jinja-Cache",
+                    HeaderValue::from_static(if hit { "hit" } else { "miss" }),
+                );
+            }
+            response
+        }
+        Err(err) => format_error_response(err),
+    }
+}
+
+/// The response to a `/format` request whose template failed to format: the syntax errors or
+/// the location of an unbalanced block as JSON, and the message of anything else.
+fn format_error_response(err: FormatError) -> Response {
+    match err {
+        FormatError::ParseError(errors) if !errors.is_empty() => json_response(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "message": FormatError::ParseError(errors.clone()).to_string(),
+                "errors": errors,
+            }),
+        ),
+        err @ FormatError::UnbalancedBlock { line, column, .. } => json_response(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({
+                "message": err.to_string(),
+                "line": line,
+                "column": column,
+            }),
+        ),
+        err => text_response(error_status(&err), err.to_string()),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ValidateRequestBody {
+    input: String,
+}
+
+async fn validate(
+    State(config): State<Arc<ServerConfig>>,
+    body: String,
+) -> Result<Response, Infallible> {
+    let Ok(request) = serde_json::from_str::<ValidateRequestBody>(&body) else {
+        return Ok(text_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid request body JSON".to_string(),
+        ));
+    };
+    let options = FormatOptions {
+        parse_timeout_micros: config.parse_timeout_micros,
+        ..FormatOptions::default()
+    };
+    // parsing may take as long as the timeout allows, off the async runtime's threads
+    let respond = move || validate_response(&request.input, &options);
+    Ok(tokio::task::spawn_blocking(respond)
+        .await
+        .expect("validating a template panicked"))
+}
+
+/// Parses the template of a `/validate` request and lists its syntax errors.
+fn validate_response(input: &str, options: &FormatOptions) -> Response {
+    match Formatter::borrowed(options).syntax_errors(input) {
+        Ok(errors) if errors.is_empty() => {
+            json_response(StatusCode::OK, serde_json::json!({ "valid": true }))
+        }
+        Ok(errors) => json_response(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "valid": false, "errors": errors }),
+        ),
+        Err(err) => text_response(error_status(&err), err.to_string()),
+    }
+}
+
+#[cfg(feature = "debug-endpoints")]
+#[derive(serde::Deserialize)]
+struct ParseRequestBody {
+    input: String,
+    /// `"sexp"` or `"json"`.
+    format: Option<String>,
+}
+
+/// `{kind, start_byte, end_byte, children}` of `node` and everything below it. The tree is walked
+/// with a cursor rather than recursively, so that deep trees can't overflow the stack.
+#[cfg(feature = "debug-endpoints")]
+fn node_json(node: tree_sitter::Node) -> serde_json::Value {
+    let node_fields = |node: tree_sitter::Node| {
+        let mut json = serde_json::json!({
+            "kind": node.kind(),
+            "start_byte": node.start_byte(),
+            "end_byte": node.end_byte(),
+        });
+        if node.is_missing() {
+            json["missing"] = true.into();
+        }
+        if node.child_count() > 0 {
+            json["children"] = serde_json::Value::Array(Vec::new());
+        }
+        json
+    };
+    let mut cursor = node.walk();
+    // the nodes from `node` down to the cursor's, with the children serialized so far
+    let mut path = vec![node_fields(node)];
+    loop {
+        if cursor.goto_first_child() {
+            path.push(node_fields(cursor.node()));
+            continue;
+        }
+        loop {
+            let json = path.pop().expect("the path ends with the cursor's node");
+            let Some(parent) = path.last_mut() else {
+                return json;
+            };
+            parent["children"]
+                .as_array_mut()
+                .expect("nodes with children have a children array")
+                .push(json);
+            if cursor.goto_next_sibling() {
+                path.push(node_fields(cursor.node()));
+                break;
+            }
+            cursor.goto_parent();
+        }
+    }
+}
+
+#[cfg(feature = "debug-endpoints")]
+async fn parse_tree(
+    State(config): State<Arc<ServerConfig>>,
+    body: String,
+) -> Result<Response, Infallible> {
+    let Ok(request) = serde_json::from_str::<ParseRequestBody>(&body) else {
+        return Ok(text_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid request body JSON".to_string(),
+        ));
+    };
+    let json = match request.format.as_deref() {
+        None | Some("sexp") => false,
+        Some("json") => true,
+        Some(other) => {
+            return Ok(text_response(
+                StatusCode::BAD_REQUEST,
+                format!("format must be \"sexp\" or \"json\", got \"{}\"", other),
+            ))
+        }
+    };
+    let options = FormatOptions {
+        parse_timeout_micros: config.parse_timeout_micros,
+        ..FormatOptions::default()
+    };
+    // parsing and serializing the tree take time, off the async runtime's threads
+    let respond = move || parse_response(&request.input, &options, json);
+    Ok(tokio::task::spawn_blocking(respond)
+        .await
+        .expect("parsing a template panicked"))
+}
+
+/// Parses the template of a `/parse` request and responds with its tree, as JSON if `json` is
+/// set and as an S-expression otherwise.
+#[cfg(feature = "debug-endpoints")]
+fn parse_response(input: &str, options: &FormatOptions, json: bool) -> Response {
+    match Formatter::borrowed(options).parse(input) {
+        Ok(tree) if json => json_response(StatusCode::OK, node_json(tree.root_node())),
+        Ok(tree) => text_response(StatusCode::OK, tree.root_node().to_sexp()),
+        Err(err) => text_response(error_status(&err), err.to_string()),
+    }
+}
+
+async fn format_batch(
+    State(config): State<Arc<ServerConfig>>,
+    body: String,
+) -> Result<Response, Infallible> {
+    let Ok(items) = serde_json::from_str::<Vec<BatchItem>>(&body) else {
+        record_outcome("bad_json");
+        return Ok(text_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid request body JSON".to_string(),
+        ));
+    };
+
+    // the templates are formatted in parallel, off the async runtime's threads
+    let format_items = move || {
+        items
+            .into_par_iter()
+            .map(|item| {
+                let formatted = item
+                    .options
+                    .options(&config)
+                    .inspect_err(|_| record_outcome("bad_options"))
+                    .and_then(|options| {
+                        measure_format(|| format_string(&item.input, &options))
+                            .map_err(|err| err.to_string())
+                    });
+                match formatted {
+                    Ok(output) => serde_json::json!({ "id": item.id, "output": output }),
+                    Err(error) => serde_json::json!({ "id": item.id, "error": error }),
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let results = tokio::task::spawn_blocking(format_items)
+        .await
+        .expect("formatting a batch panicked");
+
+    Ok(json_response(
+        StatusCode::OK,
+        serde_json::Value::from(results),
+    ))
+}
+
+/// Extensions of the archive entries that are formatted, the others are copied as they are.
+const ARCHIVE_TEMPLATE_EXTENSIONS: &[&str] = &[".html", ".j2", ".jinja", ".jinja2"];
+/// Entry added to a formatted archive listing the templates that couldn't be formatted.
+const ARCHIVE_ERRORS_ENTRY: &str = "jinjafmt-errors.json";
+
+/// Formats the templates of a zip `archive` into a new one with the same paths. Templates that
+/// fail to format are copied as they are and listed in [`ARCHIVE_ERRORS_ENTRY`]. Like request
+/// bodies, the entries may add up to at most `body_limit` bytes once decompressed.
+fn format_archive_entries(
+    config: &ServerConfig,
+    archive: &[u8],
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let invalid = |err: ZipError| {
+        record_outcome("bad_archive");
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid zip archive: {}", err),
+        )
+    };
+    let failed = |err: ZipError| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to write the zip archive: {}", err),
+        )
+    };
+    let mut archive = ZipArchive::new(Cursor::new(archive)).map_err(invalid)?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let format_options = FormatOptions {
+        parse_timeout_micros: config.parse_timeout_micros,
+        ..FormatOptions::default()
+    };
+    let mut remaining = config.body_limit as u64;
+    let mut errors = Vec::new();
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(invalid)?;
+        let name = entry.name().map_err(invalid)?.into_owned();
+        let mut options =
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        if let Some(modified) = entry.last_modified() {
+            options = options.last_modified_time(modified);
+        }
+        if let Some(mode) = entry.unix_mode() {
+            options = options.unix_permissions(mode);
+        }
+        if entry.is_dir() {
+            writer.add_directory(name, options).map_err(failed)?;
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        (&mut entry)
+            .take(remaining + 1)
+            .read_to_end(&mut contents)
+            .map_err(|err| invalid(err.into()))?;
+        if contents.len() as u64 > remaining {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Archive too large once decompressed".to_string(),
+            ));
+        }
+        remaining -= contents.len() as u64;
+        drop(entry);
+
+        let lowercase = name.to_ascii_lowercase();
+        if ARCHIVE_TEMPLATE_EXTENSIONS
+            .iter()
+            .any(|extension| lowercase.ends_with(extension))
+        {
+            let formatted = std::str::from_utf8(&contents)
+                .map_err(|_| FormatError::Utf8)
+                .and_then(|input| measure_format(|| format_string(input, &format_options)));
+            match formatted {
+                Ok(output) => contents = output.into_bytes(),
+                Err(err) => {
+                    errors.push(serde_json::json!({ "path": name, "error": err.to_string() }))
+                }
+            }
+        }
+        writer.start_file(name, options).map_err(failed)?;
+        writer
+            .write_all(&contents)
+            .map_err(|err| failed(err.into()))?;
+    }
+    if !errors.is_empty() {
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer
+            .start_file(ARCHIVE_ERRORS_ENTRY, options)
+            .map_err(failed)?;
+        let manifest = serde_json::to_vec_pretty(&errors).unwrap();
+        writer
+            .write_all(&manifest)
+            .map_err(|err| failed(err.into()))?;
+    }
+    Ok(writer.finish().map_err(failed)?.into_inner())
+}
+
+async fn format_archive(
+    State(config): State<Arc<ServerConfig>>,
+    body: Bytes,
+) -> Result<Response, Infallible> {
+    // unzipping and formatting every entry is blocking work, off the async runtime's threads
+    let entries = move || format_archive_entries(&config, &body);
+    let archive = tokio::task::spawn_blocking(entries)
+        .await
+        .expect("formatting an archive panicked");
+    Ok(match archive {
+        Ok(archive) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/zip")
+            .body(archive.into())
+            .unwrap(),
+        Err((status, message)) => text_response(status, message),
+    })
+}
+
+async fn index() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html")
+        .body(INDEX_HTML.clone().into())
+        .unwrap()
+}
+
+async fn metrics() -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(METRICS.render().into())
+        .unwrap()
+}
+
+async fn stats() -> Response {
+    json_response(StatusCode::OK, latency_stats())
+}
+
+/// Liveness probe, answers without touching the parser.
+async fn health() -> Response {
+    json_response(StatusCode::OK, serde_json::json!({ "status": "ok" }))
+}
+
+async fn version() -> Response {
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "tree_sitter_jinja2": env!("TREE_SITTER_JINJA2_VERSION"),
+        }),
+    )
+}
+
+/// Default for [`ServerConfig::body_limit`], 4 MiB.
+pub const DEFAULT_BODY_LIMIT: usize = 4 * 1024 * 1024;
+/// Default for [`ServerConfig::parse_timeout_micros`], one second.
+pub const DEFAULT_PARSE_TIMEOUT_MICROS: u64 = 1_000_000;
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Largest accepted request body in bytes, bigger ones get `413 Payload Too Large`.
+    pub body_limit: usize,
+    /// Parse timeout for every template, ones that take longer get `503 Service Unavailable`.
+    /// `0` for no limit.
+    pub parse_timeout_micros: u64,
+    /// Token that requests to the formatting endpoints must carry as `Authorization: Bearer
+    /// <token>`, others get `401 Unauthorized`. `None` disables authentication. The probes and
+    /// the index page are always open.
+    pub auth_token: Option<String>,
+    /// Origins browsers may call the server from, e.g. `https://play.example.com`. `*` allows
+    /// any origin and an empty list none but the server's own.
+    pub cors_origins: Vec<String>,
+    /// Requests a client IP may make to the formatting endpoints per minute, in bursts of up to
+    /// as many, others get `429 Too Many Requests`. `0` for no limit.
+    pub rate_limit_per_minute: u32,
+    /// Number of `/format` results kept to answer requests repeating the input and options of
+    /// an earlier one without formatting again. `0` disables the cache.
+    pub cache_size: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            body_limit: DEFAULT_BODY_LIMIT,
+            parse_timeout_micros: DEFAULT_PARSE_TIMEOUT_MICROS,
+            auth_token: None,
+            cors_origins: vec!["*".to_string()],
+            rate_limit_per_minute: 0,
+            cache_size: 0,
+        }
+    }
+}
+
+/// The reports of the latest `/format` requests that formatted, by a hash of their input,
+/// options and line range. Past `capacity` the least recently used one goes.
+struct FormatCache {
+    capacity: usize,
+    /// Randomly seeded, so that clients can't craft collisions.
+    hasher: RandomState,
+    entries: Mutex<CacheEntries>,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    /// Reports by the hash of what they were formatted from.
+    reports: HashMap<u64, CachedReport>,
+    /// Keys by last use, oldest first.
+    recency: BTreeMap<u64, u64>,
+    uses: u64,
+}
+
+/// A report along with what it was formatted from, compared on lookups since hashes collide.
+struct CachedReport {
+    input: String,
+    options: FormatOptions,
+    range: Option<RangeInclusive<usize>>,
+    report: FormatReport,
+    /// When it was last used.
+    used: u64,
+}
+
+impl FormatCache {
+    fn new(capacity: usize) -> Self {
+        FormatCache {
+            capacity,
+            hasher: RandomState::new(),
+            entries: Mutex::default(),
+        }
+    }
+
+    fn key(
+        &self,
+        input: &str,
+        options: &FormatOptions,
+        range: Option<&RangeInclusive<usize>>,
+    ) -> u64 {
+        self.hasher.hash_one((input, options, range))
+    }
+
+    fn get(
+        &self,
+        input: &str,
+        options: &FormatOptions,
+        range: Option<&RangeInclusive<usize>>,
+    ) -> Option<FormatReport> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let key = self.key(input, options, range);
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+        let cached = entries.reports.get_mut(&key).filter(|cached| {
+            cached.input == input && cached.options == *options && cached.range.as_ref() == range
+        })?;
+        entries.uses += 1;
+        entries.recency.remove(&cached.used);
+        entries.recency.insert(entries.uses, key);
+        cached.used = entries.uses;
+        Some(cached.report.clone())
+    }
+
+    /// Keeps `report`, replacing the one of another template whose key collides.
+    fn insert(
+        &self,
+        input: &str,
+        options: &FormatOptions,
+        range: Option<&RangeInclusive<usize>>,
+        report: FormatReport,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let key = self.key(input, options, range);
+        let mut entries = self.entries.lock().unwrap();
+        entries.uses += 1;
+        let used = entries.uses;
+        let cached = CachedReport {
+            input: input.to_string(),
+            options: options.clone(),
+            range: range.cloned(),
+            report,
+            used,
+        };
+        if let Some(previous) = entries.reports.insert(key, cached) {
+            entries.recency.remove(&previous.used);
+        }
+        entries.recency.insert(used, key);
+        while entries.reports.len() > self.capacity {
+            let Some((_, oldest)) = entries.recency.pop_first() else {
+                break;
+            };
+            entries.reports.remove(&oldest);
+        }
+    }
+}
+
+/// Buckets beyond this many are pruned of the full ones, which are as good as new.
+const MAX_RATE_LIMIT_BUCKETS: usize = 10_000;
+
+/// A token bucket per client IP, refilled continuously at `per_minute` tokens a minute up to
+/// `per_minute`.
+struct RateLimiter {
+    per_minute: u32,
+    /// Tokens left and when they were counted.
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// Takes a token from the bucket of `ip`, or returns the seconds until there is one.
+    fn acquire(&self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let refilled = |(tokens, counted): (f64, Instant)| {
+            let elapsed = now.saturating_duration_since(counted).as_secs_f64();
+            (tokens + elapsed * per_second).min(capacity)
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_RATE_LIMIT_BUCKETS {
+            buckets.retain(|_, bucket| refilled(*bucket) < capacity);
+        }
+        let bucket = buckets.entry(ip).or_insert((capacity, now));
+        let tokens = refilled(*bucket);
+        if tokens < 1.0 {
+            *bucket = (tokens, now);
+            return Err(((1.0 - tokens) / per_second).ceil() as u64);
+        }
+        *bucket = (tokens - 1.0, now);
+        Ok(())
+    }
+}
+
+/// Requests without a peer address, as when the router isn't served with connect info, aren't
+/// limited.
+async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if let Some(ip) = peer {
+        if let Err(retry_after) = limiter.acquire(ip, Instant::now()) {
+            record_outcome("rate_limited");
+            let mut response = text_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests".to_string(),
+            );
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+            return response;
+        }
+    }
+    next.run(request).await
+}
+
+pub fn router() -> Router {
+    router_with_config(&ServerConfig::default())
+}
+
+fn request_span(request: &Request<Body>) -> tracing::Span {
+    let body_size = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .unwrap_or("-");
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        body_size,
+    )
+}
+
+/// Compares in time independent of where the bytes differ, so the token can't be guessed a byte
+/// at a time.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn require_token(
+    State(config): State<Arc<ServerConfig>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(token) = &config.auth_token {
+        let authorized = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| constant_time_eq(given.trim().as_bytes(), token.as_bytes()));
+        if !authorized {
+            record_outcome("unauthorized");
+            let mut response = text_response(StatusCode::UNAUTHORIZED, "Unauthorized".to_string());
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return response;
+        }
+    }
+    next.run(request).await
+}
+
+/// Answers preflight requests itself, so they don't need a token. Origins that aren't valid
+/// header values are skipped with a warning.
+fn cors_layer(origins: &[String]) -> CorsLayer {
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().filter_map(|origin| {
+            HeaderValue::from_str(origin)
+                .inspect_err(|_| tracing::warn!("ignoring invalid CORS origin {:?}", origin))
+                .ok()
+        }))
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE, header::ACCEPT])
+        .expose_headers([
+            HeaderName::from_static("x-jinja-changed"),
+            HeaderName::from_static("x-jinja-lines-changed"),
+        ])
+}
+
+pub fn router_with_config(config: &ServerConfig) -> Router {
+    lazy_static::initialize(&METRICS);
+    let config = Arc::new(config.clone());
+    let limiter = Arc::new(RateLimiter {
+        per_minute: config.rate_limit_per_minute,
+        buckets: Mutex::default(),
+    });
+    let trace = TraceLayer::new_for_http()
+        .make_span_with(request_span)
+        .on_response(
+            DefaultOnResponse::new()
+                .level(Level::INFO)
+                .latency_unit(LatencyUnit::Micros),
+        );
+    let router = Router::new()
+        .route("/format", post(format_jinja))
+        .route("/format/batch", post(format_batch))
+        .route("/format/archive", post(format_archive))
+        .route("/validate", post(validate));
+    #[cfg(feature = "debug-endpoints")]
+    let router = router.route("/parse", post(parse_tree));
+    router
+        .layer(middleware::from_fn_with_state(
+            config.clone(),
+            require_token,
+        ))
+        .layer(middleware::from_fn_with_state(limiter, rate_limit))
+        // probes below are not rate limited, authenticated or logged
+        .layer(trace)
+        .route("/health", get(health))
+        .route("/version", get(version))
+        .route("/metrics", get(metrics))
+        .route("/stats", get(stats))
+        .route("/", get(index))
+        // the body limit applies to decompressed bodies, gzip bombs get `413` as well
+        .layer(DefaultBodyLimit::max(config.body_limit))
+        .layer(RequestDecompressionLayer::new())
+        .layer(CompressionLayer::new())
+        .layer(Extension(Arc::new(FormatCache::new(config.cache_size))))
+        .layer(cors_layer(&config.cors_origins))
+        .with_state(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    /// No template reaches `UnknownKeyword`, every tag name that is tracked as a block has a case
+    /// of its own, so its response is checked here rather than through `/format`.
+    #[tokio::test]
+    async fn unknown_keyword_is_unprocessable_and_named() {
+        let response = format_error_response(FormatError::UnknownKeyword("frobnicate".to_string()));
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Unknown keyword: frobnicate");
+    }
+}
@@ -0,0 +1,17 @@
+//! Entry points for running the formatter in the browser, e.g. the playground on the index
+//! page, without a round trip to the server.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{format_string, FormatOptions};
+
+/// Formats `input` with `indent_size` spaces per level. Throws an `Error` carrying the
+/// [`crate::FormatError`] message if the template can't be formatted.
+#[wasm_bindgen]
+pub fn format(input: &str, indent_size: usize) -> Result<String, JsError> {
+    let options = FormatOptions {
+        indent_size,
+        ..FormatOptions::default()
+    };
+    format_string(input, &options).map_err(|err| JsError::new(&err.to_string()))
+}
@@ -0,0 +1,147 @@
+use crate::lexer::{tokenize, Token, TokenKind};
+use crate::normalize::split_tag;
+
+/// Breaks a single-line `{{ }}` or `{% %}` tag over several lines, before each top-level filter
+/// pipe or, without pipes, after each comma of the outermost argument list. Continuation lines
+/// start with `continuation`. Returns `None` if there is nowhere to break.
+pub(crate) fn wrap_tag(tag: &str, continuation: &str) -> Option<String> {
+    let parts = split_tag(tag).filter(|parts| parts.open != "{#")?;
+    if parts.inner.contains('\n') {
+        return None;
+    }
+    let tokens = tokenize(parts.inner.trim());
+
+    let pipes: Vec<usize> = (0..tokens.len())
+        .filter(|&i| tokens[i].kind == TokenKind::Pipe && tokens[i].depth == 0)
+        .collect();
+    let breaks = if pipes.is_empty() {
+        let comma_depth = tokens
+            .iter()
+            .filter(|token| token.kind == TokenKind::Comma)
+            .map(|token| token.depth)
+            .min()?;
+        (0..tokens.len())
+            .filter(|&i| tokens[i].kind == TokenKind::Comma && tokens[i].depth == comma_depth)
+            .map(|i| i + 1)
+            .collect()
+    } else {
+        pipes
+    };
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for end in breaks.into_iter().chain([tokens.len()]) {
+        let segment: String = tokens[start..end].iter().map(|token| token.text).collect();
+        segments.push(segment.trim().to_string());
+        start = end;
+    }
+    segments.retain(|segment| !segment.is_empty());
+    if segments.len() < 2 {
+        return None;
+    }
+
+    let mut wrapped = parts.open.to_string();
+    wrapped.extend(parts.open_marker);
+    wrapped.push(' ');
+    wrapped.push_str(&segments.join(&format!("\n{}", continuation)));
+    wrapped.push(' ');
+    wrapped.extend(parts.close_marker);
+    wrapped.push_str(parts.close);
+    Some(wrapped)
+}
+
+/// Words after which a `[` starts a list literal rather than a subscript.
+pub(crate) const KEYWORDS: &[&str] = &["in", "and", "or", "not", "if", "else", "is"];
+
+/// Index of the first top-level token opening a dict or list literal.
+fn find_literal(tokens: &[Token]) -> Option<usize> {
+    let mut previous: Option<&Token> = None;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind == TokenKind::Whitespace {
+            continue;
+        }
+        if token.kind == TokenKind::Open && token.depth == 1 {
+            let literal = match token.text {
+                "{" => true,
+                "[" => match previous {
+                    None => true,
+                    Some(previous) => match previous.kind {
+                        TokenKind::Open | TokenKind::Comma | TokenKind::Pipe => true,
+                        TokenKind::Other => {
+                            KEYWORDS.contains(&previous.text)
+                                || !previous
+                                    .text
+                                    .chars()
+                                    .any(|c| c.is_alphanumeric() || c == '_')
+                        }
+                        _ => false,
+                    },
+                },
+                _ => false,
+            };
+            if literal {
+                return Some(i);
+            }
+        }
+        previous = Some(token);
+    }
+    None
+}
+
+/// Puts each entry of the first top-level dict or list literal of a tag on a line of its own,
+/// indented by `indent` plus `unit`, the last one with a trailing comma if `trailing_comma`. The
+/// closing bracket goes on a line at `indent`. Returns `None` if the tag has no such literal.
+pub(crate) fn expand_literal(
+    tag: &str,
+    indent: &str,
+    unit: &str,
+    trailing_comma: bool,
+) -> Option<String> {
+    let parts = split_tag(tag).filter(|parts| parts.open != "{#")?;
+    let tokens = tokenize(parts.inner.trim());
+    let open = find_literal(&tokens)?;
+    let close = open
+        + tokens[open..]
+            .iter()
+            .position(|token| token.kind == TokenKind::Close && token.depth == 1)?;
+
+    let mut entries = Vec::new();
+    let mut entry = String::new();
+    for token in &tokens[open + 1..close] {
+        if token.kind == TokenKind::Comma && token.depth == 1 {
+            entries.push(std::mem::take(&mut entry));
+        } else {
+            entry.push_str(token.text);
+        }
+    }
+    entries.push(entry);
+    entries.retain(|entry| !entry.trim().is_empty());
+    if entries.is_empty() {
+        return None;
+    }
+
+    let text = |tokens: &[Token]| tokens.iter().map(|token| token.text).collect::<String>();
+    let mut expanded = parts.open.to_string();
+    expanded.extend(parts.open_marker);
+    expanded.push(' ');
+    expanded.push_str(&text(&tokens[..open]));
+    expanded.push_str(tokens[open].text);
+    let last = entries.len() - 1;
+    for (i, entry) in entries.iter().enumerate() {
+        expanded.push('\n');
+        expanded.push_str(indent);
+        expanded.push_str(unit);
+        expanded.push_str(entry.trim());
+        if i < last || trailing_comma {
+            expanded.push(',');
+        }
+    }
+    expanded.push('\n');
+    expanded.push_str(indent);
+    expanded.push_str(tokens[close].text);
+    expanded.push_str(&text(&tokens[close + 1..]));
+    expanded.push(' ');
+    expanded.extend(parts.close_marker);
+    expanded.push_str(parts.close);
+    Some(expanded)
+}
@@ -0,0 +1,169 @@
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+fn jinjafmt(dir: &Path, args: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_jinjafmt"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn jinjafmt_stdin(args: &[&str], input: &str) -> (Option<i32>, String) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jinjafmt"))
+        .args(args)
+        .current_dir(dir.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+const UNFORMATTED: &str = "{% if x %}{{ x }}{% endif %}";
+const FORMATTED: &str = "{% if x %}\n  {{ x }}\n{% endif %}\n";
+
+#[test]
+fn directory_walk_skips_ignored_paths() {
+    let root = tempfile::tempdir().unwrap();
+    for dir in ["templates", "node_modules/pkg", "build"] {
+        fs::create_dir_all(root.path().join(dir)).unwrap();
+    }
+    let unformatted = "{% if x %}{{ x }}{% endif %}";
+    fs::write(root.path().join("templates/page.jinja"), unformatted).unwrap();
+    fs::write(
+        root.path().join("node_modules/pkg/vendored.jinja"),
+        unformatted,
+    )
+    .unwrap();
+    fs::write(root.path().join("build/out.j2"), unformatted).unwrap();
+    fs::write(root.path().join(".gitignore"), "node_modules/\n").unwrap();
+    fs::write(root.path().join(".ignore"), "build/\n").unwrap();
+
+    let (code, stdout) = jinjafmt(root.path(), &["--check", "."]);
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        stdout,
+        "./templates/page.jinja is not formatted\n1 unformatted, 0 unchanged, 0 failed\n"
+    );
+
+    let (code, stdout) = jinjafmt(root.path(), &["--check", "--no-ignore", "."]);
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        stdout,
+        "./build/out.j2 is not formatted\n\
+         ./node_modules/pkg/vendored.jinja is not formatted\n\
+         ./templates/page.jinja is not formatted\n\
+         3 unformatted, 0 unchanged, 0 failed\n"
+    );
+}
+
+#[test]
+fn files_are_formatted_in_place() {
+    let root = tempfile::tempdir().unwrap();
+    let (page, done) = (
+        root.path().join("page.jinja"),
+        root.path().join("done.jinja"),
+    );
+    fs::write(&page, UNFORMATTED).unwrap();
+    fs::write(&done, FORMATTED).unwrap();
+
+    let (code, stdout) = jinjafmt(root.path(), &["page.jinja", "done.jinja"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "Formatted page.jinja\n");
+    assert_eq!(fs::read_to_string(&page).unwrap(), FORMATTED);
+    assert_eq!(fs::read_to_string(&done).unwrap(), FORMATTED);
+}
+
+#[test]
+fn check_exits_with_1_for_unformatted_files() {
+    let root = tempfile::tempdir().unwrap();
+    fs::write(root.path().join("page.jinja"), UNFORMATTED).unwrap();
+    fs::write(root.path().join("done.jinja"), FORMATTED).unwrap();
+
+    assert_eq!(
+        jinjafmt(root.path(), &["--check", "done.jinja"]),
+        (Some(0), String::new())
+    );
+    assert_eq!(
+        jinjafmt(root.path(), &["--check", "page.jinja", "done.jinja"]),
+        (Some(1), "page.jinja is not formatted\n".to_string())
+    );
+    // nothing is written
+    assert_eq!(
+        fs::read_to_string(root.path().join("page.jinja")).unwrap(),
+        UNFORMATTED
+    );
+}
+
+#[test]
+fn templates_that_fail_to_format_exit_with_2() {
+    let root = tempfile::tempdir().unwrap();
+    fs::create_dir_all(root.path().join("templates")).unwrap();
+    fs::write(root.path().join("templates/page.jinja"), UNFORMATTED).unwrap();
+    fs::write(
+        root.path().join("templates/broken.jinja"),
+        "{% if x %}{% endfor %}",
+    )
+    .unwrap();
+
+    let (code, stdout) = jinjafmt(root.path(), &["--check", "templates"]);
+    assert_eq!(code, Some(2));
+    assert_eq!(
+        stdout,
+        "templates/page.jinja is not formatted\n1 unformatted, 0 unchanged, 1 failed\n"
+    );
+    let (code, _) = jinjafmt(root.path(), &["templates/broken.jinja"]);
+    assert_eq!(code, Some(2));
+
+    // the other files are still written
+    let (code, stdout) = jinjafmt(root.path(), &["templates"]);
+    assert_eq!(code, Some(2));
+    assert_eq!(
+        stdout,
+        "Formatted templates/page.jinja\n1 formatted, 0 unchanged, 1 failed\n"
+    );
+    assert_eq!(
+        fs::read_to_string(root.path().join("templates/page.jinja")).unwrap(),
+        FORMATTED
+    );
+}
+
+#[test]
+fn stdin_is_formatted_to_stdout() {
+    assert_eq!(
+        jinjafmt_stdin(&["--stdin"], UNFORMATTED),
+        (Some(0), FORMATTED.to_string())
+    );
+    assert_eq!(
+        jinjafmt_stdin(&["--stdin", "--check"], UNFORMATTED),
+        (Some(1), "<stdin> is not formatted\n".to_string())
+    );
+    assert_eq!(
+        jinjafmt_stdin(&["--stdin", "--check"], FORMATTED),
+        (Some(0), String::new())
+    );
+    assert_eq!(
+        jinjafmt_stdin(&["--stdin"], "{% if x %}"),
+        (Some(2), String::new())
+    );
+}
@@ -0,0 +1,41 @@
+use std::fs;
+
+use jinja_formatter::{
+    config::{find_config, load_config},
+    Formatter, IndentStyle,
+};
+
+#[test]
+fn config_file_is_found_and_applied() {
+    let root = tempfile::tempdir().unwrap();
+    let nested = root.path().join("templates/partials");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(
+        root.path().join(".jinjafmt.toml"),
+        "indent_size = 4\ncustom_blocks = [[\"cache\", \"endcache\"]]\n",
+    )
+    .unwrap();
+
+    let path = find_config(&nested).unwrap();
+    assert_eq!(path, root.path().join(".jinjafmt.toml"));
+
+    let options = load_config(&path).unwrap();
+    assert_eq!(options.indent_size, 4);
+    assert_eq!(options.indent_style, IndentStyle::Spaces);
+
+    let formatted = Formatter::new(options)
+        .format("{% cache %}{% if x %}{{ x }}{% endif %}{% endcache %}")
+        .unwrap();
+    assert_eq!(
+        formatted,
+        "{% cache %}\n    {% if x %}\n        {{ x }}\n    {% endif %}\n{% endcache %}\n"
+    );
+}
+
+#[test]
+fn config_file_rejects_unknown_keys() {
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().join(".jinjafmt.toml");
+    fs::write(&path, "indent = 4\n").unwrap();
+    assert!(load_config(&path).is_err());
+}
@@ -0,0 +1,1236 @@
+use jinja_formatter::{
+    FormatError, Formatter, IndentStyle, LineEnding, PipeSpacing, QuoteStyle, TrailingComma,
+    TrailingNewline,
+};
+
+#[test]
+fn tabs_round_trip_nested_blocks() {
+    let formatter = Formatter::builder()
+        .indent_style(IndentStyle::Tabs)
+        .indent_size(4)
+        .build();
+    let input = "{% for user in users %}{% if user.active %}{{ user.name }}{% endif %}{% endfor %}";
+    let expected = "{% for user in users %}\n\t{% if user.active %}\n\t\t{{ user.name }}\n\t{% endif %}\n{% endfor %}\n";
+
+    let formatted = formatter.format(input).unwrap();
+    assert_eq!(formatted, expected);
+    assert!(formatted.lines().all(|line| !line.starts_with(' ')));
+    assert_eq!(formatter.format(&formatted).unwrap(), expected);
+}
+
+#[test]
+fn block_indents_body_with_named_endblock() {
+    let input = "{% extends \"base.html\" %}{% block content %}{% if x %}{{ x }}{% endif %}{% endblock content %}";
+    let expected = "{% extends \"base.html\" %}\n{% block content %}\n  {% if x %}\n    {{ x }}\n  {% endif %}\n{% endblock content %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+}
+
+#[test]
+fn inline_set_does_not_open_a_block() {
+    let input = "{% if x %}{% set y = x | upper %}{{ y }}{% endif %}";
+    let expected = "{% if x %}\n  {% set y = x | upper %}\n  {{ y }}\n{% endif %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+}
+
+#[test]
+fn block_set_indents_until_endset() {
+    let input = "{% if x %}{% set nav %}{{ x }}{% endset %}{{ nav }}{% endif %}";
+    let expected =
+        "{% if x %}\n  {% set nav %}\n    {{ x }}\n  {% endset %}\n  {{ nav }}\n{% endif %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+
+    let input = "{% if x %}{% set nav | replace(old=\"a\") %}{{ x }}{% endset %}{% endif %}";
+    let expected = "{% if x %}\n  {% set nav | replace(old=\"a\") %}\n    {{ x }}\n  {% endset %}\n{% endif %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+}
+
+#[test]
+fn with_block_with_arguments_indents_body() {
+    let input = "{% for item in items %}{% with foo=item.foo, baz=item.baz %}{{ foo }}{{ baz }}{% endwith %}{% endfor %}";
+    let expected = "{% for item in items %}\n  {% with foo=item.foo, baz=item.baz %}\n    {{ foo }}{{ baz }}\n  {% endwith %}\n{% endfor %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+}
+
+#[test]
+fn autoescape_block_is_idempotent() {
+    let formatter = Formatter::default();
+    let input = "{% autoescape false %}{% if html %}{{ html }}{% endif %}{% endautoescape %}";
+    let expected =
+        "{% autoescape false %}\n  {% if html %}\n    {{ html }}\n  {% endif %}\n{% endautoescape %}\n";
+    let formatted = formatter.format(input).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+
+    let input = "{% autoescape enabled and not trusted %}{{ x }}{% endautoescape %}";
+    let expected = "{% autoescape enabled and not trusted %}\n  {{ x }}\n{% endautoescape %}\n";
+    assert_eq!(formatter.format(input).unwrap(), expected);
+}
+
+#[test]
+fn raw_block_body_is_copied_verbatim() {
+    let formatter = Formatter::default();
+    let body = "\n{% for x in xs %}\n      {{ x }\n{#  #}  {%- endfor\n";
+    let input = format!(
+        "{{% if docs %}}{{% raw %}}{}{{% endraw %}}{{% endif %}}",
+        body
+    );
+    let expected = format!(
+        "{{% if docs %}}\n  {{% raw %}}{}  {{% endraw %}}\n{{% endif %}}\n",
+        body
+    );
+    assert_eq!(formatter.format(&input).unwrap(), expected);
+
+    let input = "{% raw -%} {{ inline }} {%- endraw %}";
+    assert_eq!(formatter.format(input).unwrap(), format!("{}\n", input));
+}
+
+#[test]
+fn trans_block_dedents_pluralize() {
+    let input = "{% if user %}{% trans count=user.messages|length %}{{ count }}{% pluralize %}{{ count }}{% endtrans %}{% endif %}";
+    let expected = "{% if user %}\n  {% trans count=user.messages|length %}\n    {{ count }}\n  {% pluralize %}\n    {{ count }}\n  {% endtrans %}\n{% endif %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+}
+
+#[test]
+fn apply_block_indents_like_filter() {
+    let input = "{% for x in xs %}{% apply upper %}{{ x }}{% endapply %}{% filter upper %}{{ x }}{% endfilter %}{% endfor %}";
+    let expected = "{% for x in xs %}\n  {% apply upper %}\n    {{ x }}\n  {% endapply %}\n  {% filter upper %}\n    {{ x }}\n  {% endfilter %}\n{% endfor %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+}
+
+#[test]
+fn custom_blocks_are_indented() {
+    let input = "{% cache 60 %}{% if x %}{{ x }}{% endif %}{% endcache %}";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "{% cache 60 %}\n{% if x %}\n  {{ x }}\n{% endif %}\n{% endcache %}\n"
+    );
+
+    let formatter = Formatter::builder()
+        .custom_blocks(vec![("cache".to_string(), "endcache".to_string())])
+        .build();
+    assert_eq!(
+        formatter.format(input).unwrap(),
+        "{% cache 60 %}\n  {% if x %}\n    {{ x }}\n  {% endif %}\n{% endcache %}\n"
+    );
+}
+
+#[test]
+fn whitespace_control_marker_spacing_is_normalized() {
+    let formatter = Formatter::default();
+    let cases = [
+        (
+            "{%for x in xs%}{{x}}{%endfor%}",
+            "{% for x in xs %}\n  {{ x }}\n{% endfor %}\n",
+        ),
+        (
+            "{%-for x in xs%}{{-x}}{%-endfor%}",
+            "{%- for x in xs %}\n  {{- x }}\n{%- endfor %}\n",
+        ),
+        (
+            "{%for x in xs  -%}{{x   -}}{%endfor-%}",
+            "{% for x in xs -%}\n  {{ x -}}\n{% endfor -%}\n",
+        ),
+        (
+            "{%-for x in xs-%}{{-x-}}{%+   endfor   +%}",
+            "{%- for x in xs -%}\n  {{- x -}}\n{%+ endfor +%}\n",
+        ),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(formatter.format(input).unwrap(), expected);
+    }
+}
+
+#[test]
+fn expression_delimiter_spacing_is_normalized() {
+    let formatter = Formatter::default();
+    let cases = [
+        ("{{foo}}", "{{ foo }}\n"),
+        ("{{ foo }}", "{{ foo }}\n"),
+        ("{{   foo|join(',')  }}", "{{ foo | join(',') }}\n"),
+        ("{{-foo-}}", "{{- foo -}}\n"),
+        ("{{-   foo }}", "{{- foo }}\n"),
+        ("{{ foo  -}}", "{{ foo -}}\n"),
+        ("{{a}}{{  b }}", "{{ a }}{{ b }}\n"),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(formatter.format(input).unwrap(), expected);
+    }
+}
+
+#[test]
+fn statement_delimiter_spacing_is_normalized() {
+    let input =
+        "{%if x%}{%  for y in x  %}{{ y }}{% endfor%}{%else   %}{%-   set z = 1%}{%endif -%}";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "{% if x %}\n  {% for y in x %}\n    {{ y }}\n  {% endfor %}\n{% else %}\n  {%- set z = 1 %}\n{% endif -%}\n"
+    );
+}
+
+#[test]
+fn text_between_tags_is_preserved() {
+    let input = "<ul>\n  {% for x in xs %}\n    <li>{{ x }}</li>\n  {% endfor %}\n</ul>\n";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "<ul>\n{% for x in xs %}\n    <li>{{ x }}</li>\n{% endfor %}\n</ul>\n"
+    );
+}
+
+#[test]
+fn blank_line_runs_are_collapsed() {
+    let input = "\n\n<p>a</p>\n\n\n\n\n\n<p>b</p>\n{% if x %}\n<p>c</p>\n\n\n{% endif %}\n\n\n";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "<p>a</p>\n\n<p>b</p>\n{% if x %}\n<p>c</p>\n\n{% endif %}\n"
+    );
+    let formatter = Formatter::builder().max_blank_lines(2).build();
+    assert_eq!(
+        formatter.format("a\n\n\n\n\n\n{{ b }}").unwrap(),
+        "a\n\n\n{{ b }}\n"
+    );
+    let formatter = Formatter::builder().max_blank_lines(0).build();
+    assert_eq!(
+        formatter.format("a\n\n\n\n\n\n{{ b }}").unwrap(),
+        "a\n{{ b }}\n"
+    );
+}
+
+#[test]
+fn templates_without_tags_are_kept_as_they_are() {
+    let input = "\n<html>\n  <body>\n\n\n\n\t<p>{ not jinja }</p>\n  </body>\n</html>\n\n";
+    let cases = [
+        (TrailingNewline::Insert, input.trim_end().to_string() + "\n"),
+        (TrailingNewline::Remove, input.trim_end().to_string()),
+        (TrailingNewline::Preserve, input.to_string()),
+    ];
+    for (mode, expected) in cases {
+        let formatter = Formatter::builder().trailing_newline(mode).build();
+        assert_eq!(formatter.format(input).unwrap(), expected, "{:?}", mode);
+    }
+    let formatter = Formatter::builder()
+        .trailing_newline(TrailingNewline::Preserve)
+        .build();
+    assert_eq!(formatter.format("  no newline").unwrap(), "  no newline");
+    let crlf = "<p>\r\n\r\n\r\n  text\r\n</p>\r\n";
+    assert_eq!(Formatter::default().format(crlf).unwrap(), crlf);
+}
+
+#[test]
+fn whitespace_lines_ending_templates_without_tags_are_dropped() {
+    let cases = [
+        (TrailingNewline::Insert, "a\n"),
+        (TrailingNewline::Remove, "a"),
+        (TrailingNewline::Preserve, "a\n\n"),
+    ];
+    for (mode, expected) in cases {
+        let formatter = Formatter::builder().trailing_newline(mode).build();
+        let formatted = formatter.format("a\n  \n").unwrap();
+        assert_eq!(formatted, expected, "{:?}", mode);
+        assert_eq!(
+            formatter.format(&formatted).unwrap(),
+            expected,
+            "{:?}",
+            mode
+        );
+    }
+    let formatter = Formatter::builder().trim_trailing_whitespace(false).build();
+    assert_eq!(formatter.format("a \n\t\n").unwrap(), "a\n");
+}
+
+#[test]
+fn trailing_whitespace_is_trimmed() {
+    let input = "<ul>  \n{% for x in xs %}\t\n  <li>{{ x }}</li> \n  {# a  \n  b #}  \n{% endfor %}  \n</ul>   ";
+    let formatted = Formatter::default().format(input).unwrap();
+    assert_eq!(
+        formatted,
+        "<ul>\n{% for x in xs %}\n  <li>{{ x }}</li>\n  {# a\n  b #}\n{% endfor %}\n</ul>\n"
+    );
+    assert!(formatted.lines().all(|line| line.trim_end() == line));
+    assert_eq!(Formatter::default().format("a \nb\t\n").unwrap(), "a\nb\n");
+    assert_eq!(
+        Formatter::default()
+            .format("{{ \"keep  \n me\" }}  \n{% raw %}\n  x  \n{% endraw %}\n{# fmt: off #}\ny  \n{# fmt: on #}")
+            .unwrap(),
+        "{{ \"keep  \n me\" }}\n{% raw %}\n  x  \n{% endraw %}\n{# fmt: off #}\ny  \n{# fmt: on #}\n"
+    );
+    let formatter = Formatter::builder().trim_trailing_whitespace(false).build();
+    assert_eq!(
+        formatter.format("<p>  \n{{ x }}\n").unwrap(),
+        "<p>  \n{{ x }}\n"
+    );
+}
+
+#[test]
+fn blank_lines_between_blocks_are_kept() {
+    assert_eq!(
+        Formatter::default()
+            .format(include_str!("fixtures/macros.input.jinja"))
+            .unwrap(),
+        "{% macro input(name, value='', type='text') %}\n  {{- name -}}{{ value }}\n{% endmacro %}\n\n\
+         {% macro label(text) %}\n  {{ text }}\n{% endmacro %}\n\n\
+         {% macro field(name) %}\n  {{ label(name) }}\n  {{ input(name) }}\n{% endmacro %}\n"
+    );
+}
+
+#[test]
+fn trailing_newline_modes() {
+    let cases = [
+        (TrailingNewline::Insert, "{{ x }}", "{{ x }}\n"),
+        (TrailingNewline::Insert, "{{ x }}\n\n\n", "{{ x }}\n"),
+        (TrailingNewline::Remove, "{{ x }}", "{{ x }}"),
+        (TrailingNewline::Remove, "{{ x }}\n\n\n", "{{ x }}"),
+        (TrailingNewline::Preserve, "{{ x }}", "{{ x }}"),
+        (TrailingNewline::Preserve, "{{ x }}\n", "{{ x }}\n"),
+        (TrailingNewline::Preserve, "{{ x }}\n\n\n", "{{ x }}\n\n\n"),
+        (TrailingNewline::Preserve, "{{ x }}  \n \n", "{{ x }}\n\n"),
+        (
+            TrailingNewline::Preserve,
+            "{{ x }}\r\n\r\n\r\n",
+            "{{ x }}\r\n\r\n\r\n",
+        ),
+        (TrailingNewline::Preserve, "\n\n", ""),
+    ];
+    for (mode, input, expected) in cases {
+        let formatter = Formatter::builder().trailing_newline(mode).build();
+        assert_eq!(formatter.format(input).unwrap(), expected, "{:?}", mode);
+    }
+}
+
+#[test]
+fn line_endings_follow_the_input_or_the_option() {
+    let input = "{% if x %}\r\n<p>{{ x }}</p>\r\n{% endif %}\n";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "{% if x %}\r\n<p>{{ x }}</p>\r\n{% endif %}\r\n"
+    );
+    let formatter = Formatter::builder().line_ending(LineEnding::Lf).build();
+    assert_eq!(
+        formatter.format(input).unwrap(),
+        "{% if x %}\n<p>{{ x }}</p>\n{% endif %}\n"
+    );
+    let formatter = Formatter::builder().line_ending(LineEnding::Crlf).build();
+    assert_eq!(
+        formatter
+            .format("{% if x %}\n{{ x }}\n{% endif %}")
+            .unwrap(),
+        "{% if x %}\r\n  {{ x }}\r\n{% endif %}\r\n"
+    );
+}
+
+#[test]
+fn comments_are_indented_and_normalized() {
+    let input = "{% for x in xs %}\n{#x#}\n{% if x %}\n      {#-   deep  -#}\n{#\n  keep\n  lines\n#}\n{% endif %}\n{% endfor %}";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "{% for x in xs %}\n  {# x #}\n  {% if x %}\n    {#- deep -#}\n    {#\n  keep\n  lines\n#}\n  {% endif %}\n{% endfor %}\n"
+    );
+}
+
+#[test]
+fn multi_line_comments_keep_their_layout() {
+    assert_eq!(
+        Formatter::default()
+            .format(include_str!("fixtures/multiline_comment.input.jinja"))
+            .unwrap(),
+        "{% if user %}\n  {# Render the greeting:\n     - the name comes from the profile\n     - fall back to the login #}\n<p>Hello {{ user.name or user.login }}</p>\n{% endif %}\n"
+    );
+}
+
+#[test]
+fn text_and_expressions_on_a_line_stay_together() {
+    let input = "<ul>\n{% for item in items %}\n  <li>Item: {{ item.name }} ({{item.count}})</li>\n{% endfor %}\n</ul>\n{% for x in xs %}<li>{{ x }}</li>{% endfor %}";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "<ul>\n{% for item in items %}\n  <li>Item: {{ item.name }} ({{ item.count }})</li>\n{% endfor %}\n</ul>\n{% for x in xs %}\n  <li>{{ x }}</li>\n{% endfor %}\n"
+    );
+}
+
+#[test]
+fn parse_timeout_is_reported() {
+    let input = format!(
+        "{}{{{{ x }}}}{}",
+        "{% for x in xs %}{% if x %}".repeat(5000),
+        "{% endif %}{% endfor %}".repeat(5000)
+    );
+    let formatter = Formatter::builder().parse_timeout_micros(1).build();
+    assert_eq!(formatter.format(&input), Err(FormatError::Timeout));
+    // a parser that timed out must start over on the next template
+    let mut parser = jinja_formatter::new_parser();
+    assert_eq!(
+        formatter.format_with_parser(&mut parser, &input),
+        Err(FormatError::Timeout)
+    );
+    assert_eq!(
+        Formatter::default()
+            .format_with_parser(&mut parser, "{{ x }}")
+            .unwrap(),
+        "{{ x }}\n"
+    );
+    // and so must the parser `format` reuses on this thread
+    assert_eq!(Formatter::default().format("{{ x }}").unwrap(), "{{ x }}\n");
+}
+
+/// Records the writes it gets, failing once it has `capacity` bytes.
+struct Chunks {
+    chunks: Vec<String>,
+    capacity: usize,
+}
+
+impl std::io::Write for Chunks {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len: usize = self.chunks.iter().map(String::len).sum();
+        if len + buf.len() > self.capacity {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.chunks.push(String::from_utf8(buf.to_vec()).unwrap());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn output_is_streamed_to_writers() {
+    let input = "\u{FEFF}{% for x in xs %}\r\n{% set a = 1 %}\r\n{% set bb = 2 %}\r\n{{ x }}\r\n{% endfor %}\r\n"
+        .repeat(3);
+    let formatter = Formatter::builder()
+        .preserve_bom(true)
+        .align_set_assignments(true)
+        .build();
+    let mut writer = Chunks {
+        chunks: Vec::new(),
+        capacity: usize::MAX,
+    };
+    formatter.format_to_writer(&input, &mut writer).unwrap();
+    assert!(writer.chunks.len() > 3, "{:?}", writer.chunks);
+    assert_eq!(writer.chunks.concat(), formatter.format(&input).unwrap());
+
+    let mut full = Chunks {
+        chunks: Vec::new(),
+        capacity: 10,
+    };
+    assert_eq!(
+        formatter.format_to_writer(&input, &mut full),
+        Err(FormatError::Io("disk full".to_string()))
+    );
+}
+
+#[test]
+fn edited_templates_format_like_new_ones() {
+    let formatter = Formatter::default();
+    let mut template = formatter
+        .parse_template("{% for x in xs %}{{ x }}{% endfor %}")
+        .unwrap();
+    // (range, replacement) of each edit, as an editor sends them
+    let edits = [
+        (17..17, "{% if x %}"),
+        (34..34, "{% endif %}"),
+        (27..30, "x.name | upper"),
+        (0..0, "{% raw %}{% if %}{% endraw %}\n"),
+        (9..17, "{{ x "),
+        (9..14, ""),
+    ];
+    for (range, text) in edits {
+        formatter.reparse(&mut template, range, text).unwrap();
+        let source = template.source().to_string();
+        assert_eq!(
+            template.tree().root_node().to_sexp(),
+            formatter.parse(&source).unwrap().root_node().to_sexp(),
+            "{source:?}"
+        );
+        assert_eq!(
+            formatter.format_parsed(&template),
+            formatter.format_report(&source),
+            "{source:?}"
+        );
+    }
+
+    let mut template = formatter
+        .parse_template("\u{FEFF}{% if a %}\r\n{{ a }}\r\n{% endif %}\r\n")
+        .unwrap();
+    formatter.reparse(&mut template, 9..10, "b").unwrap();
+    assert_eq!(
+        template.source(),
+        "\u{FEFF}{% if b %}\r\n{{ a }}\r\n{% endif %}\r\n"
+    );
+    assert_eq!(
+        formatter.format_parsed(&template).unwrap().output,
+        "{% if b %}\r\n  {{ a }}\r\n{% endif %}\r\n"
+    );
+    formatter.reparse(&mut template, 11..13, "").unwrap();
+    assert!(matches!(
+        formatter.format_parsed(&template),
+        Err(FormatError::ParseError(_))
+    ));
+}
+
+#[test]
+fn include_import_and_from_are_not_blocks() {
+    let tags = [
+        "{% include \"x.html\" %}",
+        "{% include [\"a.html\", \"b.html\"] ignore missing %}",
+        "{% import \"m.html\" as m %}",
+        "{% from \"m.html\" import foo, bar with context %}",
+    ];
+    let mut parser = jinja_formatter::new_parser();
+    for tag in tags {
+        let tree = parser.parse(tag, None).unwrap();
+        let statement = tree.root_node().child(0).unwrap();
+        assert_eq!(
+            jinja_formatter::peek_jinja_stmt_keyword(statement, tag.as_bytes()),
+            None,
+            "{}",
+            tag
+        );
+    }
+
+    let input = "{% block body %}\n{%include \"x.html\"%}\n{% if x %}\n{%-   import \"m.html\" as m-%}\n{%from \"m.html\" import foo   %}\n{% endif %}\n{% endblock %}\n";
+    let expected = "{% block body %}\n  {% include \"x.html\" %}\n  {% if x %}\n    {%- import \"m.html\" as m -%}\n    {% from \"m.html\" import foo %}\n  {% endif %}\n{% endblock %}\n";
+    let formatter = Formatter::default();
+    assert_eq!(formatter.format(input).unwrap(), expected);
+    assert_eq!(formatter.format(expected).unwrap(), expected);
+}
+
+#[test]
+fn do_is_an_indented_statement() {
+    let tag = "{% do items.append(x) %}";
+    let mut parser = jinja_formatter::new_parser();
+    let tree = parser.parse(tag, None).unwrap();
+    let statement = tree.root_node().child(0).unwrap();
+    assert_eq!(
+        jinja_formatter::peek_jinja_stmt_keyword(statement, tag.as_bytes()),
+        None
+    );
+
+    let input = "{% for x in xs %}\n{%do  items.append(x)%}\n{% if x %}{%-   do seen.update({'a':x,'b':1})-%}{% endif %}\n{% endfor %}\n{%do\n  f() %}\n";
+    let expected = "{% for x in xs %}\n  {% do items.append(x) %}\n  {% if x %}\n    {%- do seen.update({'a':x, 'b':1}) -%}\n  {% endif %}\n{% endfor %}\n{% do f() %}\n";
+    let formatter = Formatter::default();
+    assert_eq!(formatter.format(input).unwrap(), expected);
+    assert_eq!(formatter.format(expected).unwrap(), expected);
+}
+
+#[test]
+fn extends_stays_first_and_flush_left() {
+    let formatter = Formatter::default();
+    let input = "{%extends \"base.html\"%}\n{% block title %}{{ title }}{% endblock %}\n{% block body %}\n{% if x %}\n<p>{{ x }}</p>\n{% endif %}\n{% endblock %}\n";
+    let report = formatter.format_report(input).unwrap();
+    assert_eq!(
+        report.output,
+        "{% extends \"base.html\" %}\n{% block title %}\n  {{ title }}\n{% endblock %}\n{% block body %}\n  {% if x %}\n<p>{{ x }}</p>\n  {% endif %}\n{% endblock %}\n"
+    );
+    assert!(report.warnings.is_empty());
+    let report = formatter
+        .format_report("{# layout #}\n{% extends \"base.html\" %}\n")
+        .unwrap();
+    assert!(report.warnings.is_empty());
+
+    let report = formatter
+        .format_report("<!DOCTYPE html>\n{% extends \"base.html\" %}\n")
+        .unwrap();
+    assert_eq!(
+        report.warnings,
+        ["`extends` on line 2 is not the first tag of the template"]
+    );
+    let report = formatter
+        .format_report("{% set x = 1 %}\n{% extends \"base.html\" %}\n")
+        .unwrap();
+    assert_eq!(report.warnings.len(), 1);
+}
+
+#[test]
+fn for_else_dedents_like_if_else() {
+    let formatter = Formatter::default();
+    let formatted = formatter
+        .format(include_str!("fixtures/for_else.input.jinja"))
+        .unwrap();
+    assert_eq!(
+        formatted,
+        "<ul>\n{% for user in users %}\n  {{ user.name }}\n{% else %}\n  {% if query %}\n    {{ \"No users match\" }} {{ query }}\n  {% else %}\n    {# no users yet #}\n  {% endif %}\n{% endfor %}\n</ul>\n"
+    );
+    assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    assert!(matches!(
+        formatter.format("{% for x in xs %}{% else %}{% elif y %}{% endfor %}"),
+        Err(FormatError::UnbalancedBlock { .. })
+    ));
+}
+
+#[test]
+fn nested_elif_else_chains_line_up() {
+    let formatted = Formatter::default()
+        .format(include_str!("fixtures/nested_elif.input.jinja"))
+        .unwrap();
+    let columns: Vec<(usize, &str)> = formatted
+        .lines()
+        .map(|line| (line.len() - line.trim_start().len(), line.trim_start()))
+        .collect();
+    assert_eq!(
+        columns,
+        [
+            (0, "{% if a %}"),
+            (2, "{% if b %}"),
+            (4, "{{ ab }}"),
+            (2, "{% elif c %}"),
+            (4, "{% if d %}"),
+            (6, "{{ acd }}"),
+            (4, "{% elif e %}"),
+            (6, "{{ ace }}"),
+            (4, "{% else %}"),
+            (6, "{{ ac }}"),
+            (4, "{% endif %}"),
+            (4, "{{ after_inner }}"),
+            (2, "{% else %}"),
+            (4, "{{ a_only }}"),
+            (2, "{% endif %}"),
+            (0, "{% elif f %}"),
+            (2, "{{ f }}"),
+            (0, "{% else %}"),
+            (2, "{{ none }}"),
+            (0, "{% endif %}"),
+        ]
+    );
+}
+
+#[test]
+fn long_tags_are_wrapped() {
+    let formatter = Formatter::builder().max_line_width(40).build();
+    let cases = [
+        (
+            "{% if x %}{{ items|map(attribute='name')|join(', ')|upper }}{% endif %}",
+            "{% if x %}\n  {{ items\n    | map(attribute='name')\n    | join(', ')\n    | upper }}\n{% endif %}\n",
+        ),
+        (
+            "{{- url_for('static', filename='css/site.css', v=1) -}}",
+            "{{- url_for('static',\n  filename='css/site.css',\n  v=1) -}}\n",
+        ),
+        (
+            "{% set label = 'a | b, c' ~ some_long_variable_name %}",
+            "{% set label = 'a | b, c' ~ some_long_variable_name %}\n",
+        ),
+        ("{{ short|upper }}", "{{ short | upper }}\n"),
+    ];
+    for (input, expected) in cases {
+        let formatted = formatter.format(input).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    }
+}
+
+#[test]
+fn only_the_selected_lines_are_formatted() {
+    let input = "{% if a %}\n{{a}}\n{% for x in xs %}\n{{x}}\n{%if x%}\n{{ y }}\n{%endif%}\n{% endfor %}\n{% endif %}\n";
+    let formatter = Formatter::default();
+    assert_eq!(
+        formatter.format_range(input, 4..=6).unwrap(),
+        "{% if a %}\n{{a}}\n{% for x in xs %}\n    {{ x }}\n    {% if x %}\n      {{ y }}\n{%endif%}\n{% endfor %}\n{% endif %}\n"
+    );
+    assert_eq!(
+        formatter.format_range(input, 1..=100).unwrap(),
+        formatter.format(input).unwrap()
+    );
+}
+
+#[test]
+fn raw_bodies_keep_their_bytes_when_the_tags_move() {
+    let input = "{% for a in as %}\n{% for b in bs %}\n{% raw %}\n{{ not\n      indented }}\n\t{% if %}\n{% endraw %}\n{% endfor %}\n{% endfor %}\n";
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        "{% for a in as %}\n  {% for b in bs %}\n    {% raw %}\n{{ not\n      indented }}\n\t{% if %}\n    {% endraw %}\n  {% endfor %}\n{% endfor %}\n"
+    );
+}
+
+#[test]
+fn filter_pipe_spacing_is_normalized() {
+    let input = "{{ name|upper|trim }}\n{{ name  |  upper |trim }}\n{{ x|replace('a|b', \"|\")|join(y|string) }}\n{% filter upper|trim %}{% endfilter %}\n{% set s = a|b %}\n";
+    let cases = [
+        (
+            PipeSpacing::Spaces,
+            "{{ name | upper | trim }}\n{{ name | upper | trim }}\n{{ x | replace('a|b', \"|\") | join(y | string) }}\n{% filter upper | trim %}\n{% endfilter %}\n{% set s = a|b %}\n",
+        ),
+        (
+            PipeSpacing::Tight,
+            "{{ name|upper|trim }}\n{{ name|upper|trim }}\n{{ x|replace('a|b', \"|\")|join(y|string) }}\n{% filter upper|trim %}\n{% endfilter %}\n{% set s = a|b %}\n",
+        ),
+        (
+            PipeSpacing::Preserve,
+            "{{ name|upper|trim }}\n{{ name  |  upper |trim }}\n{{ x|replace('a|b', \"|\")|join(y|string) }}\n{% filter upper|trim %}\n{% endfilter %}\n{% set s = a|b %}\n",
+        ),
+    ];
+    for (spacing, expected) in cases {
+        let formatter = Formatter::builder().pipe_spacing(spacing).build();
+        assert_eq!(formatter.format(input).unwrap(), expected, "{:?}", spacing);
+    }
+}
+
+#[test]
+fn binary_operators_are_spaced() {
+    let formatter = Formatter::builder().operator_spacing(true).build();
+    let input = "{% if x==1 and y!=-2 %}\n{{ a+b*2 }}{{ x|default(0)+1 }}\n{% endif %}\n";
+    assert_eq!(
+        formatter.format(input).unwrap(),
+        "{% if x == 1 and y != -2 %}\n  {{ a + b * 2 }}{{ x | default(0) + 1 }}\n{% endif %}\n"
+    );
+    let cases = [
+        ("{{ -x }}", "{{ -x }}"),
+        ("{{ f(a-1, -b) }}", "{{ f(a - 1, -b) }}"),
+        ("{{ loop.index-1 }}", "{{ loop.index - 1 }}"),
+        ("{{ 1e-5*x }}", "{{ 1e-5 * x }}"),
+        ("{{ a-b -}}", "{{ a - b -}}"),
+        ("{{ x if a<b else -y }}", "{{ x if a < b else -y }}"),
+        ("{% set x = a  or  b %}", "{% set x = a or b %}"),
+        (
+            "{% macro m(a, *args, **kwargs) %}{% endmacro %}",
+            "{% macro m(a, *args, **kwargs) %}\n{% endmacro %}",
+        ),
+        ("{{ \"a==b\"~c }}", "{{ \"a==b\"~c }}"),
+        ("{{ 'x+y' ~ a+b }}", "{{ 'x+y' ~ a + b }}"),
+    ];
+    for (input, expected) in cases {
+        let formatted = formatter.format(input).unwrap();
+        assert_eq!(formatted, format!("{}\n", expected), "{}", input);
+        assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    }
+    assert_eq!(
+        Formatter::default().format("{{ a+b }}").unwrap(),
+        "{{ a+b }}\n"
+    );
+}
+
+#[test]
+fn commas_are_followed_by_one_space() {
+    let cases = [
+        ("{{ url_for('x',a=1,b=2) }}", "{{ url_for('x', a=1, b=2) }}"),
+        ("{{ f(a ,b  ,  c) }}", "{{ f(a, b, c) }}"),
+        ("{{ 'a,b'|split(',') }}", "{{ 'a,b' | split(',') }}"),
+        ("{{ f('a , b',\"c,d\") }}", "{{ f('a , b', \"c,d\") }}"),
+        ("{{ {'a':1,'b':[1,2]} }}", "{{ {'a':1, 'b':[1, 2]} }}"),
+        (
+            "{% for k,v in items %}{% endfor %}",
+            "{% for k, v in items %}\n{% endfor %}",
+        ),
+        (
+            "{% macro m(a,b=2) %}{% endmacro %}",
+            "{% macro m(a, b=2) %}\n{% endmacro %}",
+        ),
+        ("{{ f(a,\n     b) }}", "{{ f(a,\n     b) }}"),
+    ];
+    for (input, expected) in cases {
+        let formatted = Formatter::default().format(input).unwrap();
+        assert_eq!(formatted, format!("{}\n", expected), "{}", input);
+        assert_eq!(Formatter::default().format(&formatted).unwrap(), formatted);
+    }
+    assert_eq!(
+        Formatter::builder()
+            .comma_spacing(false)
+            .build()
+            .format("{{ f(a,b) }}")
+            .unwrap(),
+        "{{ f(a,b) }}\n"
+    );
+}
+
+#[test]
+fn macro_signatures_are_normalized() {
+    let input = "{%macro  card (title,body=none , level = 2, classes=['a','b'], attrs={'x':1}, label=\"a = b\" )%}{{ title }}{%endmacro%}\n";
+    let formatted = Formatter::default().format(input).unwrap();
+    assert_eq!(
+        formatted,
+        "{% macro card(title, body=none, level=2, classes=['a', 'b'], attrs={'x':1}, label=\"a = b\") %}\n  {{ title }}\n{% endmacro %}\n"
+    );
+    assert_eq!(Formatter::default().format(&formatted).unwrap(), formatted);
+
+    let formatter = Formatter::builder().macro_default_spacing(true).build();
+    let formatted = formatter
+        .format("{% macro foo(a,b=1 , c=x==1) %}{% endmacro %}")
+        .unwrap();
+    assert_eq!(
+        formatted,
+        "{% macro foo(a, b = 1, c = x==1) %}\n{% endmacro %}\n"
+    );
+    assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn call_signatures_are_normalized_and_bodies_indented() {
+    let input = "{%call( user , size=2 ) render_user ( users ,\"a\", wide = true )%}{% if user %}{% if user.admin %}{{ user.name }}{% endif %}{% endif %}{%endcall%}";
+    let expected = "{% call(user, size=2) render_user(users, \"a\", wide=true) %}\n  {% if user %}\n    {% if user.admin %}\n      {{ user.name }}\n    {% endif %}\n  {% endif %}\n{% endcall %}\n";
+    let formatter = Formatter::default();
+    assert_eq!(formatter.format(input).unwrap(), expected);
+    assert_eq!(formatter.format(expected).unwrap(), expected);
+    assert_eq!(
+        formatter
+            .format("{% call   render(a=1) %}x{% endcall %}")
+            .unwrap(),
+        "{% call render(a=1) %}\n  x\n{% endcall %}\n"
+    );
+
+    let formatter = Formatter::builder().macro_default_spacing(true).build();
+    let formatted = formatter
+        .format("{% call(a=1) ns.render( b,c=2 ) %}x{% endcall %}")
+        .unwrap();
+    assert_eq!(
+        formatted,
+        "{% call(a = 1) ns.render(b, c = 2) %}\n  x\n{% endcall %}\n"
+    );
+    assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn four_nested_loops_indent_by_depth() {
+    let input = "{% for a in as %}{% for b in a %}{% if b %}{% for c in b %}{% for d in c %}{{ d }}{% if d %}{{ a }}{% endif %}{% endfor %}{{ c }}{% endfor %}{% endif %}{% endfor %}{% endfor %}{{ done }}";
+    // (nesting level, tag) of each output line
+    let lines = [
+        (0, "{% for a in as %}"),
+        (1, "{% for b in a %}"),
+        (2, "{% if b %}"),
+        (3, "{% for c in b %}"),
+        (4, "{% for d in c %}"),
+        (5, "{{ d }}"),
+        (5, "{% if d %}"),
+        (6, "{{ a }}"),
+        (5, "{% endif %}"),
+        (4, "{% endfor %}"),
+        (4, "{{ c }}"),
+        (3, "{% endfor %}"),
+        (2, "{% endif %}"),
+        (1, "{% endfor %}"),
+        (0, "{% endfor %}"),
+        (0, "{{ done }}"),
+    ];
+    for indent_size in [2, 3, 4] {
+        let formatter = Formatter::builder().indent_size(indent_size).build();
+        let expected: String = lines
+            .iter()
+            .map(|(level, tag)| format!("{}{}\n", " ".repeat(level * indent_size), tag))
+            .collect();
+        assert_eq!(formatter.format(input).unwrap(), expected);
+        assert_eq!(formatter.format(&expected).unwrap(), expected);
+    }
+}
+
+#[test]
+fn very_deep_nesting_is_indented() {
+    let depth = 100;
+    let input = format!(
+        "{}{{{{ x }}}}{}",
+        "{% if x %}".repeat(depth),
+        "{% endif %}".repeat(depth)
+    );
+    for (formatter, unit) in [
+        (Formatter::builder().indent_size(8).build(), " ".repeat(8)),
+        (
+            Formatter::builder().indent_style(IndentStyle::Tabs).build(),
+            "\t".to_string(),
+        ),
+    ] {
+        let formatted = formatter.format(&input).unwrap();
+        let lines: Vec<&str> = formatted.lines().collect();
+        assert_eq!(lines[depth], format!("{}{{{{ x }}}}", unit.repeat(depth)));
+        assert_eq!(
+            lines[depth + 1],
+            format!("{}{{% endif %}}", unit.repeat(depth - 1))
+        );
+    }
+}
+
+#[test]
+fn html_without_tags_is_indented() {
+    let formatter = Formatter::builder().indent_html(true).build();
+    let expected = "<div>\n  <p>x</p>\n</div>\n";
+    assert_eq!(
+        formatter.format("<div>\n<p>x</p>\n</div>").unwrap(),
+        expected
+    );
+    assert_eq!(
+        formatter.format("<div>\n<p>{{ x }}</p>\n</div>").unwrap(),
+        expected.replace('x', "{{ x }}")
+    );
+    assert_eq!(formatter.format(expected).unwrap(), expected);
+}
+
+#[test]
+fn html_elements_indent_with_blocks() {
+    let formatter = Formatter::builder().indent_html(true).build();
+    let input = "<ul>\n{% for x in xs %}\n<li>\n<span>{{ x }}</span><br>\n<img src=\"{{ x }}\" />\n</li>\n{% endfor %}\n</ul>\n<div><div>\n{% if a %}<section class=\"a\">{% else %}<section>{% endif %}\n<p>{{ a }}</p>\n</section>\n</div></div>\n";
+    let expected = "<ul>\n  {% for x in xs %}\n    <li>\n      <span>{{ x }}</span><br>\n      <img src=\"{{ x }}\" />\n    </li>\n  {% endfor %}\n</ul>\n<div><div>\n    {% if a %}\n      <section class=\"a\">\n    {% else %}\n      <section>\n    {% endif %}\n      <p>{{ a }}</p>\n    </section>\n</div></div>\n";
+    assert_eq!(formatter.format(input).unwrap(), expected);
+    assert_eq!(formatter.format(expected).unwrap(), expected);
+    // without the option, lines starting with text are kept as they are
+    assert_eq!(
+        Formatter::default()
+            .format("<ul>\n<li>{{ x }}</li>\n</ul>\n")
+            .unwrap(),
+        "<ul>\n<li>{{ x }}</li>\n</ul>\n"
+    );
+}
+
+#[test]
+fn pre_text_is_kept_byte_for_byte() {
+    let input = "{% block body %}\n<pre>\n  total:   {{total}}\n    {{ rows|join(\"\\n\") }}\n\n\n      {% if more %}...{% endif %}\n</pre>\n{% endblock %}\n";
+    let expected = "{% block body %}\n<pre>\n  total:   {{ total }}\n    {{ rows | join(\"\\n\") }}\n\n\n      {% if more %}...{% endif %}\n</pre>\n{% endblock %}\n";
+    let formatter = Formatter::default();
+    assert_eq!(formatter.format(input).unwrap(), expected);
+    assert_eq!(formatter.format(expected).unwrap(), expected);
+
+    // only the line of the `<pre>` tag itself is indented
+    let formatter = Formatter::builder().indent_html(true).build();
+    let expected = expected.replace("\n<pre>", "\n  <pre>");
+    assert_eq!(formatter.format(input).unwrap(), expected);
+    assert_eq!(formatter.format(&expected).unwrap(), expected);
+}
+
+#[test]
+fn leading_bom_is_stripped() {
+    let input = "\u{FEFF}{% if x %}{{ x }}{% endif %}";
+    let expected = "{% if x %}\n  {{ x }}\n{% endif %}\n";
+    assert_eq!(Formatter::default().format(input).unwrap(), expected);
+    assert_eq!(
+        Formatter::default()
+            .format("\u{FEFF}plain text\r\n")
+            .unwrap(),
+        "plain text\r\n"
+    );
+
+    let formatter = Formatter::builder().preserve_bom(true).build();
+    let formatted = formatter.format(input).unwrap();
+    assert_eq!(formatted, format!("\u{FEFF}{}", expected));
+    assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    assert_eq!(formatter.format(expected).unwrap(), expected);
+}
+
+#[test]
+fn invalid_utf8_is_an_error() {
+    let sources: [&[u8]; 4] = [
+        b"{% if x %}\xff{% endif %}",
+        b"{{ x }}\xc3\x28",
+        b"{% if x %}{# \xfe #}{{ \"\xff\" }}{% endif %}",
+        b"{% for x in f('\x80') %}{% endfor %}",
+    ];
+    let mut parser = jinja_formatter::new_parser();
+    for source in sources {
+        let tree = parser.parse(source, None).unwrap();
+        assert!(
+            matches!(
+                Formatter::default().format_jinja_node(tree.root_node(), source),
+                Err(FormatError::Utf8)
+            ),
+            "{:?}",
+            source
+        );
+    }
+}
+
+#[test]
+fn best_effort_keeps_broken_tags_as_they_are() {
+    let input = "{% if x %}\n{{y}}\n    {{ x }\n{%for a in b%}{{a}}{%endfor%}\n{% endif %}\n";
+    assert!(matches!(
+        Formatter::default().format(input),
+        Err(FormatError::ParseError(_))
+    ));
+
+    let formatter = Formatter::builder().best_effort(true).build();
+    let report = formatter.format_report(input).unwrap();
+    assert_eq!(
+        report.output,
+        "{% if x %}\n  {{ y }}\n    {{ x }\n  {% for a in b %}\n    {{ a }}\n  {% endfor %}\n{% endif %}\n"
+    );
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!((report.errors[0].line, report.errors[0].column), (3, 5));
+    assert_eq!(formatter.format(&report.output).unwrap(), report.output);
+
+    // the broken tag swallows the `{% endif %}`
+    let input = "{% if x %}{{ \"a'b\" }}{{y}}{% endif %}";
+    let report = formatter.format_report(input).unwrap();
+    assert_eq!(report.output, format!("{}\n", input));
+    assert_eq!(report.errors.len(), 1);
+
+    let report = formatter.format_report("{{x}}").unwrap();
+    assert!(report.errors.is_empty());
+}
+
+#[test]
+fn string_quotes_are_normalized() {
+    let input = r#"<a title='text'>
+{# 'comment' #}
+{% set x = 'foo' %}
+{{ "bar" ~ 'say "hi"' ~ x | replace('\\', "/") }}
+"#;
+    let double = Formatter::builder().quote_style(QuoteStyle::Double).build();
+    let single = Formatter::builder().quote_style(QuoteStyle::Single).build();
+    // a literal holding both kinds of quotes keeps its own
+    assert_eq!(
+        double.format(input).unwrap(),
+        r#"<a title='text'>
+{# 'comment' #}
+{% set x = "foo" %}
+{{ "bar" ~ 'say "hi"' ~ x | replace("\\", "/") }}
+"#
+    );
+    assert_eq!(
+        single.format(input).unwrap(),
+        r#"<a title='text'>
+{# 'comment' #}
+{% set x = 'foo' %}
+{{ 'bar' ~ 'say "hi"' ~ x | replace('\\', '/') }}
+"#
+    );
+    assert_eq!(Formatter::default().format(input).unwrap(), input);
+    for formatter in [double, single] {
+        let formatted = formatter.format(input).unwrap();
+        assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    }
+}
+
+#[test]
+fn spacing_passes_leave_quotes_alone() {
+    let input = r#"{%set   greeting="Hello"%}
+{%  set name='world|"x"'   %}
+{{greeting|default( 'hi' ,"there" )~name|replace("a", 'b')}}
+{%if x=='|'%}{{ {'a': "b", "c": 'd|e', 'long_key_name': "a long value to force wrapping"} | tojson }}{%endif%}
+"#;
+    let quotes = |text: &str| {
+        text.chars()
+            .filter(|c| *c == '\'' || *c == '"')
+            .collect::<String>()
+    };
+    let formatters = [
+        Formatter::default(),
+        Formatter::builder()
+            .pipe_spacing(PipeSpacing::Tight)
+            .build(),
+        Formatter::builder()
+            .max_line_width(40)
+            .align_set_assignments(true)
+            .build(),
+        Formatter::builder()
+            .max_line_width(40)
+            .expand_literals(true)
+            .build(),
+    ];
+    for formatter in formatters {
+        let formatted = formatter.format(input).unwrap();
+        assert_eq!(quotes(&formatted), quotes(input), "{}", formatted);
+    }
+    assert_eq!(
+        Formatter::default().format(input).unwrap(),
+        r#"{% set   greeting="Hello" %}
+{% set name='world|"x"' %}
+{{ greeting | default( 'hi', "there" )~name | replace("a", 'b') }}
+{% if x=='|' %}
+  {{ {'a': "b", "c": 'd|e', 'long_key_name': "a long value to force wrapping"} | tojson }}
+{% endif %}
+"#
+    );
+}
+
+#[test]
+fn long_literals_are_expanded() {
+    let formatter = Formatter::builder()
+        .max_line_width(40)
+        .expand_literals(true)
+        .build();
+    let cases = [
+        (
+            "{% if x %}{% set config = {'title': 'A {braced} title', 'tags': ['a', 'b'], 'n': 1} %}{% endif %}",
+            "{% if x %}\n  {% set config = {\n    'title': 'A {braced} title',\n    'tags': ['a', 'b'],\n    'n': 1,\n  } %}\n{% endif %}\n",
+        ),
+        (
+            "{% for item in ['first', 'second', 'third', 'fourth'] %}{% endfor %}",
+            "{% for item in [\n  'first',\n  'second',\n  'third',\n  'fourth',\n] %}\n{% endfor %}\n",
+        ),
+        ("{{ {'a': 1, 'b': 2} }}", "{{ {'a': 1, 'b': 2} }}\n"),
+        (
+            "{{ items[loop.index0] | default('nothing at all') }}",
+            "{{ items[loop.index0]\n  | default('nothing at all') }}\n",
+        ),
+    ];
+    for (input, expected) in cases {
+        let formatted = formatter.format(input).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    }
+}
+
+#[test]
+fn trailing_commas_of_inline_literals() {
+    let input =
+        r#"{% set x = [1, 2 ,] %}{{ {"a": (1, 2), 'b,]': [(3,), []], "c": (d)} }}{{ f(a, b,) }}"#;
+    let kept = "{% set x = [1, 2,] %}\n{{ {\"a\": (1, 2), 'b,]': [(3,), []], \"c\": (d)} }}{{ f(a, b,) }}\n";
+    let added = "{% set x = [1, 2,] %}\n{{ {\"a\": (1, 2,), 'b,]': [(3,), [],], \"c\": (d),} }}{{ f(a, b,) }}\n";
+    let dropped = "{% set x = [1, 2] %}\n{{ {\"a\": (1, 2), 'b,]': [(3,), []], \"c\": (d)} }}{{ f(a, b,) }}\n";
+    for (trailing_comma, expected) in [
+        (TrailingComma::Preserve, kept),
+        (TrailingComma::Always, added),
+        (TrailingComma::Never, dropped),
+    ] {
+        let formatter = Formatter::builder().trailing_comma(trailing_comma).build();
+        let formatted = formatter.format(input).unwrap();
+        assert_eq!(formatted, expected, "{trailing_comma:?}");
+        assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    }
+
+    // literals written over several lines are kept as they are
+    for input in [
+        "{% set x = [\n  1,\n  2,\n] %}\n",
+        "{% set x = [\n  1,\n  2\n] %}\n",
+    ] {
+        for trailing_comma in [TrailingComma::Always, TrailingComma::Never] {
+            let formatter = Formatter::builder().trailing_comma(trailing_comma).build();
+            assert_eq!(formatter.format(input).unwrap(), input);
+        }
+    }
+}
+
+#[test]
+fn trailing_commas_of_expanded_literals() {
+    let input = "{% set items = ['first', 'second', 'third', 'fourth',] %}";
+    let expanded = |last: &str| {
+        format!("{{% set items = [\n  'first',\n  'second',\n  'third',\n  'fourth{last}\n] %}}\n")
+    };
+    for (trailing_comma, expected) in [
+        (TrailingComma::Preserve, expanded("',")),
+        (TrailingComma::Always, expanded("',")),
+        (TrailingComma::Never, expanded("'")),
+    ] {
+        let formatter = Formatter::builder()
+            .max_line_width(40)
+            .expand_literals(true)
+            .trailing_comma(trailing_comma)
+            .build();
+        let formatted = formatter.format(input).unwrap();
+        assert_eq!(formatted, expected, "{trailing_comma:?}");
+        assert_eq!(formatter.format(&formatted).unwrap(), formatted);
+    }
+}
+
+#[test]
+fn syntax_errors_are_reported_with_locations() {
+    let err = Formatter::default()
+        .format("{% if x %}\n  {{ x \n{% endif %}")
+        .unwrap_err();
+    let FormatError::ParseError(errors) = err else {
+        panic!("expected a parse error, got {:?}", err);
+    };
+    assert_eq!((errors[0].line, errors[0].column), (2, 3));
+    assert_eq!(errors[0].snippet, "{{ x \n{% endif %}");
+}
+
+#[test]
+fn mismatched_end_tags_are_reported() {
+    let formatter = Formatter::default();
+    assert_eq!(
+        formatter.format("{% if x %}{{ x }}{% endfor %}"),
+        Err(FormatError::UnbalancedBlock {
+            expected: Some("endif".to_string()),
+            found: Some("endfor".to_string()),
+            line: 1,
+            column: 18,
+        })
+    );
+    assert_eq!(
+        formatter.format("{% for x in xs %}{% if x %}{{ x }}{% endif %}{% endfor %}{% endif %}"),
+        Err(FormatError::UnbalancedBlock {
+            expected: None,
+            found: Some("endif".to_string()),
+            line: 1,
+            column: 58,
+        })
+    );
+    assert_eq!(
+        formatter.format("{% for x in xs %}{% if x %}{{ x }}{% endif %}"),
+        Err(FormatError::UnbalancedBlock {
+            expected: Some("endfor".to_string()),
+            found: None,
+            line: 1,
+            column: 1,
+        })
+    );
+}
+
+#[test]
+fn missing_endif_points_at_its_opening_tag() {
+    let err = Formatter::default()
+        .format("<ul>\n{% for x in xs %}\n  {% if x %}\n    {{ x }}\n{% endfor %}\n</ul>\n")
+        .unwrap_err();
+    assert_eq!(
+        err,
+        FormatError::UnbalancedBlock {
+            expected: Some("endif".to_string()),
+            found: Some("endfor".to_string()),
+            line: 5,
+            column: 1,
+        }
+    );
+    let err = Formatter::default()
+        .format("{% block body %}\n  {% if x %}\n{% endblock %}\n{% if y %}\n")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Unbalanced block: expected `endif` but found `endblock` at line 3, column 1"
+    );
+    let err = Formatter::default()
+        .format("<p>\n  {% if x %}\n    {{ x }}\n</p>\n")
+        .unwrap_err();
+    assert_eq!(
+        err,
+        FormatError::UnbalancedBlock {
+            expected: Some("endif".to_string()),
+            found: None,
+            line: 2,
+            column: 3,
+        }
+    );
+}
+
+#[test]
+fn stray_else_or_endif_is_an_error_not_a_panic() {
+    let formatter = Formatter::default();
+    for stray in ["endif", "else", "elif"] {
+        assert_eq!(
+            formatter.format(&format!("{{% {} %}}", stray)),
+            Err(FormatError::UnbalancedBlock {
+                expected: None,
+                found: Some(stray.to_string()),
+                line: 1,
+                column: 1,
+            })
+        );
+    }
+    assert_eq!(
+        formatter.format("{% for x in xs %}{% elif x %}{% endfor %}"),
+        Err(FormatError::UnbalancedBlock {
+            expected: Some("endfor".to_string()),
+            found: Some("elif".to_string()),
+            line: 1,
+            column: 18,
+        })
+    );
+}
+
+#[test]
+fn empty_input_formats_to_empty_output() {
+    assert_eq!(Formatter::default().format(""), Ok(String::new()));
+}
+
+#[test]
+fn whitespace_only_input_formats_to_empty_output() {
+    let formatter = Formatter::default();
+    for input in [" ", "\n", "\n\n   \n", "\t \r\n"] {
+        assert_eq!(formatter.format(input), Ok(String::new()));
+    }
+}
+
+#[test]
+fn fmt_off_regions_are_copied_verbatim() {
+    let formatter = Formatter::default();
+    let input = "{% for row in rows %}\n{% if row %}{{ row }}{% endif %}\n{# fmt: off #}\n<tr>   {% if row.a %}{{row.a}}\n          {% endif %}</tr>\n{# fmt: on #}\n{% if row %}{{ row }}{% endif %}\n{% endfor %}";
+    let expected = "{% for row in rows %}\n  {% if row %}\n    {{ row }}\n  {% endif %}\n  {# fmt: off #}\n<tr>   {% if row.a %}{{row.a}}\n          {% endif %}</tr>\n  {# fmt: on #}\n  {% if row %}\n    {{ row }}\n  {% endif %}\n{% endfor %}\n";
+    assert_eq!(formatter.format(input).unwrap(), expected);
+
+    let input = "{% if x %}{{ x }}{% endif %}\n{# fmt: off #}\n{% if y %}  {{ y }}{% endif %}\n\n";
+    let expected =
+        "{% if x %}\n  {{ x }}\n{% endif %}\n{# fmt: off #}\n{% if y %}  {{ y }}{% endif %}\n";
+    assert_eq!(formatter.format(input).unwrap(), expected);
+}
@@ -0,0 +1,70 @@
+use std::{env, fs, path::Path};
+
+use jinja_formatter::{config::load_config, Formatter};
+
+/// Formats every `tests/fixtures/*.input.jinja` and compares it with the `*.expected.jinja`
+/// next to it, using the options in a `*.jinjafmt.toml` next to it if there is one. Run with
+/// `UPDATE_EXPECT=1` to write the current output as the expectation. Formatting the output again
+/// and streaming it with `format_to_writer` must give the same.
+#[test]
+fn fixtures_match_expectations() {
+    let update = env::var_os("UPDATE_EXPECT").is_some_and(|value| value == "1");
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut inputs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".input.jinja"))
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty(), "no fixtures in {}", dir.display());
+
+    let mut mismatches = Vec::new();
+    for input_path in inputs {
+        let expected_path = input_path
+            .to_string_lossy()
+            .replace(".input.jinja", ".expected.jinja");
+        let options_path = Path::new(
+            &input_path
+                .to_string_lossy()
+                .replace(".input.jinja", ".jinjafmt.toml"),
+        )
+        .to_path_buf();
+        let formatter = match options_path.is_file() {
+            true => Formatter::new(load_config(&options_path).unwrap()),
+            false => Formatter::default(),
+        };
+        let input = fs::read_to_string(&input_path).unwrap();
+        let formatted = formatter
+            .format(&input)
+            .unwrap_or_else(|err| panic!("{}: {}", input_path.display(), err));
+        assert_eq!(
+            formatter.format(&formatted).unwrap(),
+            formatted,
+            "{} formatted twice",
+            input_path.display()
+        );
+        let mut streamed = Vec::new();
+        formatter.format_to_writer(&input, &mut streamed).unwrap();
+        assert_eq!(
+            String::from_utf8(streamed).unwrap(),
+            formatted,
+            "{} streamed",
+            input_path.display()
+        );
+        if update {
+            fs::write(&expected_path, &formatted).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path)
+            .unwrap_or_else(|err| panic!("{}: {} (run with UPDATE_EXPECT=1)", expected_path, err));
+        if formatted != expected {
+            mismatches.push(format!(
+                "{}:\n--- expected\n{}\n--- formatted\n{}",
+                input_path.display(),
+                expected,
+                formatted
+            ));
+        }
+    }
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
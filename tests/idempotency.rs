@@ -0,0 +1,29 @@
+use std::{fs, path::Path};
+
+use jinja_formatter::Formatter;
+
+/// Every `*.input.jinja` template under `tests/fixtures` must be a fixed point of the formatter
+/// once formatted.
+#[test]
+fn formatting_fixtures_is_idempotent() {
+    let formatter = Formatter::default();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.to_string_lossy().ends_with(".input.jinja"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixtures in {}", dir.display());
+
+    for path in paths {
+        let input = fs::read_to_string(&path).unwrap();
+        let once = formatter
+            .format(&input)
+            .unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        let twice = formatter
+            .format(&once)
+            .unwrap_or_else(|err| panic!("{} (second pass): {}", path.display(), err));
+        assert_eq!(twice, once, "{} is not idempotent", path.display());
+    }
+}
@@ -0,0 +1,721 @@
+use std::{
+    io::{Cursor, Read, Write},
+    net::SocketAddr,
+};
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{Request, StatusCode},
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use http_body_util::BodyExt;
+use jinja_formatter::server;
+use tower::ServiceExt;
+
+async fn post(uri: &str, body: &str) -> (StatusCode, String) {
+    let request = Request::post(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = server::router().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn get(uri: &str) -> (StatusCode, String) {
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let response = server::router().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn health_is_ok() {
+    assert_eq!(
+        get("/health").await,
+        (StatusCode::OK, r#"{"status":"ok"}"#.to_string())
+    );
+}
+
+#[tokio::test]
+async fn version_reports_crate_and_grammar() {
+    let (status, body) = get("/version").await;
+    assert_eq!(status, StatusCode::OK);
+    let version: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+    assert!(version["tree_sitter_jinja2"]
+        .as_str()
+        .is_some_and(|grammar| grammar.starts_with("0.")));
+}
+
+#[tokio::test]
+async fn oversized_body_is_rejected() {
+    let router = server::router_with_config(&server::ServerConfig {
+        body_limit: 64,
+        ..Default::default()
+    });
+    let body = format!(r#"{{"input": "{}"}}"#, "{{ x }}".repeat(20));
+    let request = Request::post("/format")
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[tokio::test]
+async fn gzip_bodies_round_trip() {
+    let inputs: Vec<_> = (0..200)
+        .map(|i| serde_json::json!({ "id": i, "input": "{% if x %}{{x}}{% endif %}" }))
+        .collect();
+    let body = serde_json::Value::from(inputs).to_string();
+    let request = Request::post("/format/batch")
+        .header("Content-Type", "application/json")
+        .header("Content-Encoding", "gzip")
+        .header("Accept-Encoding", "gzip")
+        .body(Body::from(gzip(body.as_bytes())))
+        .unwrap();
+    let response = server::router().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Encoding"], "gzip");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let results: serde_json::Value =
+        serde_json::from_reader(GzDecoder::new(body.as_ref())).unwrap();
+    assert_eq!(
+        results[199]["output"],
+        "{% if x %}\n  {{ x }}\n{% endif %}\n"
+    );
+}
+
+#[tokio::test]
+async fn body_limit_applies_to_decompressed_size() {
+    let router = server::router_with_config(&server::ServerConfig {
+        body_limit: 1024,
+        ..Default::default()
+    });
+    let body = format!(r#"{{"input": "{}"}}"#, " ".repeat(100_000));
+    let compressed = gzip(body.as_bytes());
+    assert!(compressed.len() < 1024);
+    let request = Request::post("/format")
+        .header("Content-Type", "application/json")
+        .header("Content-Encoding", "gzip")
+        .body(Body::from(compressed))
+        .unwrap();
+    let response = router.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn slow_parse_is_unavailable() {
+    let router = server::router_with_config(&server::ServerConfig {
+        parse_timeout_micros: 1,
+        ..Default::default()
+    });
+    let input = format!(
+        "{}{}",
+        "{% if x %}".repeat(5000),
+        "{% endif %}".repeat(5000)
+    );
+    let body = serde_json::json!({ "input": input }).to_string();
+    for uri in ["/format", "/validate"] {
+        let request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.clone()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn auth_token_is_required() {
+    let router = server::router_with_config(&server::ServerConfig {
+        auth_token: Some("secret".to_string()),
+        ..Default::default()
+    });
+    let format = |authorization: Option<&str>| {
+        let mut request = Request::post("/format").header("Content-Type", "application/json");
+        if let Some(authorization) = authorization {
+            request = request.header("Authorization", authorization);
+        }
+        request.body(Body::from(r#"{"input": "{{x}}"}"#)).unwrap()
+    };
+    for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+        let response = router.clone().oneshot(format(authorization)).await.unwrap();
+        assert_eq!(
+            response.status(),
+            StatusCode::UNAUTHORIZED,
+            "{authorization:?}"
+        );
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+    }
+    let response = router
+        .clone()
+        .oneshot(format(Some("Bearer secret")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let health = Request::get("/health").body(Body::empty()).unwrap();
+    let response = router.oneshot(health).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn burst_from_one_address_is_limited() {
+    let router = server::router_with_config(&server::ServerConfig {
+        rate_limit_per_minute: 3,
+        ..Default::default()
+    });
+    let request = |uri: &str, peer: &str| {
+        let mut request = Request::post(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"input": "{{x}}"}"#))
+            .unwrap();
+        let peer: SocketAddr = peer.parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request
+    };
+    let mut statuses = Vec::new();
+    for _ in 0..5 {
+        let response = router
+            .clone()
+            .oneshot(request("/format", "203.0.113.7:50000"))
+            .await
+            .unwrap();
+        statuses.push(response.status());
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            assert_eq!(response.headers()["Retry-After"], "20");
+        }
+    }
+    assert_eq!(
+        statuses,
+        [
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::TOO_MANY_REQUESTS,
+        ]
+    );
+
+    // other clients keep their own budget, and probes aren't limited
+    let response = router
+        .clone()
+        .oneshot(request("/format", "198.51.100.2:50000"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut health = Request::get("/health").body(Body::empty()).unwrap();
+    let peer: SocketAddr = "203.0.113.7:50001".parse().unwrap();
+    health.extensions_mut().insert(ConnectInfo(peer));
+    let response = router.oneshot(health).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn cors_preflight_succeeds() {
+    let router = server::router_with_config(&server::ServerConfig {
+        auth_token: Some("secret".to_string()),
+        cors_origins: vec!["https://play.example.com".to_string()],
+        ..Default::default()
+    });
+    let preflight = |origin: &str| {
+        Request::options("/format")
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "POST")
+            .header(
+                "Access-Control-Request-Headers",
+                "authorization,content-type",
+            )
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = router
+        .clone()
+        .oneshot(preflight("https://play.example.com"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(
+        headers["Access-Control-Allow-Origin"],
+        "https://play.example.com"
+    );
+    assert!(headers["Access-Control-Allow-Methods"]
+        .to_str()
+        .unwrap()
+        .contains("POST"));
+
+    let response = router
+        .oneshot(preflight("https://other.example.com"))
+        .await
+        .unwrap();
+    assert!(!response
+        .headers()
+        .contains_key("Access-Control-Allow-Origin"));
+}
+
+#[tokio::test]
+async fn cors_allows_any_origin_by_default() {
+    let request = Request::post("/format")
+        .header("Origin", "http://localhost:5173")
+        .header("Content-Type", "application/json")
+        .body(Body::from(r#"{"input": "{{x}}"}"#))
+        .unwrap();
+    let response = server::router().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers["Access-Control-Allow-Origin"], "*");
+    assert!(headers["Access-Control-Expose-Headers"]
+        .to_str()
+        .unwrap()
+        .contains("x-jinja-changed"));
+}
+
+#[tokio::test]
+async fn metrics_count_outcomes() {
+    post("/format", r#"{"input": "{{ x }}"}"#).await;
+    post("/format", "not json").await;
+    let (status, body) = get("/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(
+        body.contains(r#"format_requests_total{outcome="ok"}"#),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains(r#"format_requests_total{outcome="bad_json"}"#),
+        "{}",
+        body
+    );
+    assert!(body.contains("format_duration_seconds_bucket"), "{}", body);
+}
+
+#[tokio::test]
+async fn stats_report_latency_percentiles() {
+    for _ in 0..5 {
+        post("/format", r#"{"input": "{% if x %}{{ x }}{% endif %}"}"#).await;
+    }
+    let (status, body) = get("/stats").await;
+    assert_eq!(status, StatusCode::OK);
+    let stats: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(stats["window_seconds"], 60);
+    // other tests format in the same process too
+    assert!(stats["count"].as_u64().unwrap() >= 5, "{}", body);
+    let latency = &stats["latency_seconds"];
+    let (p50, p95, p99) = (
+        latency["p50"].as_f64().unwrap(),
+        latency["p95"].as_f64().unwrap(),
+        latency["p99"].as_f64().unwrap(),
+    );
+    assert!(0.0 <= p50 && p50 <= p95 && p95 <= p99, "{}", body);
+}
+
+#[tokio::test]
+async fn format_range_keeps_other_lines() {
+    assert_eq!(
+        post(
+            "/format",
+            r#"{"input": "{% if x %}\n{{a}}\n{{b}}\n{% endif %}\n", "range": {"start_line": 3, "end_line": 3}}"#
+        )
+        .await,
+        (
+            StatusCode::OK,
+            "{% if x %}\n{{a}}\n  {{ b }}\n{% endif %}\n".to_string()
+        )
+    );
+    let (status, _) = post(
+        "/format",
+        r#"{"input": "{{ x }}", "range": {"start_line": 2, "end_line": 1}}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn missing_endif_reports_position() {
+    let (status, body) = post(
+        "/format",
+        r#"{"input": "<ul>\n  {% if x %}\n    {{ x }}\n</ul>\n"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        error,
+        serde_json::json!({
+            "message": "Unbalanced block: missing `endif` at line 2, column 3",
+            "line": 2,
+            "column": 3,
+        })
+    );
+}
+
+#[tokio::test]
+async fn validate_reports_syntax_errors() {
+    assert_eq!(
+        post("/validate", r#"{"input": "{% if x %}{{ x }}{% endif %}"}"#).await,
+        (StatusCode::OK, r#"{"valid":true}"#.to_string())
+    );
+    let (status, body) = post(
+        "/validate",
+        r#"{"input": "{% if x %}\n  {{ x }\n{% endif %}"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["valid"], false);
+    let errors = body["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        (&errors[0]["line"], &errors[0]["column"]),
+        (&2.into(), &3.into())
+    );
+    assert_eq!(errors[0]["snippet"], "{{ x }");
+}
+
+#[cfg(feature = "debug-endpoints")]
+#[tokio::test]
+async fn parse_returns_the_tree() {
+    assert_eq!(
+        post("/parse", r#"{"input": "{{ x }}"}"#).await,
+        (
+            StatusCode::OK,
+            "(source_file (expression (expression_begin) identifier: (identifier) (expression_end)))"
+                .to_string()
+        )
+    );
+    let (status, body) = post("/parse", r#"{"input": "a{{ x }}", "format": "json"}"#).await;
+    assert_eq!(status, StatusCode::OK);
+    let tree: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(tree["kind"], "source_file");
+    let expression = &tree["children"][0];
+    assert_eq!(expression["kind"], "expression");
+    assert_eq!(
+        (&expression["start_byte"], &expression["end_byte"]),
+        (&1.into(), &8.into())
+    );
+    assert_eq!(expression["children"][1]["kind"], "identifier");
+}
+
+#[cfg(not(feature = "debug-endpoints"))]
+#[tokio::test]
+async fn parse_is_only_served_with_debug_endpoints() {
+    let (status, _) = post("/parse", r#"{"input": "{{ x }}"}"#).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn format_archive_formats_templates() {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default();
+    writer.add_directory("templates/", options).unwrap();
+    for (name, contents) in [
+        ("templates/index.html", "{% if x %}{{x}}{% endif %}"),
+        ("templates/broken.j2", "{% if x %}"),
+        ("static/app.js", "let a = `{{x}}`;"),
+    ] {
+        writer.start_file(name, options).unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+    }
+    let archive = writer.finish().unwrap().into_inner();
+
+    let request = Request::post("/format/archive")
+        .header("Content-Type", "application/zip")
+        .body(Body::from(archive))
+        .unwrap();
+    let response = server::router().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/zip");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let mut archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+    let mut read = |name: &str| {
+        let mut contents = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    };
+    assert_eq!(
+        read("templates/index.html"),
+        "{% if x %}\n  {{ x }}\n{% endif %}\n"
+    );
+    assert_eq!(read("templates/broken.j2"), "{% if x %}");
+    assert_eq!(read("static/app.js"), "let a = `{{x}}`;");
+    let errors: serde_json::Value = serde_json::from_str(&read("jinjafmt-errors.json")).unwrap();
+    assert_eq!(errors.as_array().unwrap().len(), 1);
+    assert_eq!(errors[0]["path"], "templates/broken.j2");
+    assert!(archive.by_name("templates/").unwrap().is_dir());
+}
+
+#[tokio::test]
+async fn format_archive_rejects_invalid_zip() {
+    let (status, body) = post("/format/archive", "not a zip").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.starts_with("Invalid zip archive"), "{body}");
+}
+
+#[tokio::test]
+async fn repeated_requests_hit_the_cache() {
+    let router = server::router_with_config(&server::ServerConfig {
+        cache_size: 1,
+        ..Default::default()
+    });
+    let format = |body: &'static str| {
+        let router = router.clone();
+        async move {
+            let request = Request::post("/format")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let response = router.oneshot(request).await.unwrap();
+            let cache = response.headers()["X-Jinja-Cache"].clone();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (cache, String::from_utf8(body.to_vec()).unwrap())
+        }
+    };
+    let two = r#"{"input": "{% if x %}{{x}}{% endif %}"}"#;
+    let four = r#"{"input": "{% if x %}{{x}}{% endif %}", "indent_size": 4}"#;
+    let (cache, first) = format(two).await;
+    assert_eq!(cache, "miss");
+    assert_eq!(format(two).await, ("hit".parse().unwrap(), first.clone()));
+
+    let (cache, indented) = format(four).await;
+    assert_eq!(cache, "miss");
+    assert_eq!(indented, "{% if x %}\n    {{ x }}\n{% endif %}\n");
+    // the cache holds one result, so the first one was evicted
+    assert_eq!(format(two).await, ("miss".parse().unwrap(), first));
+}
+
+#[tokio::test]
+async fn distinct_requests_never_share_cache_entries() {
+    let router = server::router_with_config(&server::ServerConfig {
+        cache_size: 100,
+        ..Default::default()
+    });
+    let format = |body: String| {
+        let router = router.clone();
+        async move {
+            let request = Request::post("/format")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let response = router.oneshot(request).await.unwrap();
+            let cache = response.headers()["X-Jinja-Cache"].clone();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            (cache, String::from_utf8(body.to_vec()).unwrap())
+        }
+    };
+    // the same inputs with other options or ranges, and inputs differing in one byte
+    let mut bodies = Vec::new();
+    for input in [
+        "{% if x %}\n{{x}}\n{% endif %}",
+        "{% if y %}\n{{x}}\n{% endif %}",
+    ] {
+        for options in [
+            "",
+            r#", "indent_size": 4"#,
+            r#", "range": {"start_line": 2, "end_line": 2}"#,
+            r#", "range": {"start_line": 1, "end_line": 1}"#,
+        ] {
+            bodies.push(format!(r#"{{"input": {input:?}{options}}}"#));
+        }
+    }
+    let mut outputs = Vec::new();
+    for body in &bodies {
+        let (cache, output) = format(body.clone()).await;
+        assert_eq!(cache, "miss", "{}", body);
+        outputs.push(output);
+    }
+    for (body, output) in bodies.iter().zip(&outputs) {
+        assert_eq!(
+            format(body.clone()).await,
+            ("hit".parse().unwrap(), output.clone()),
+            "{}",
+            body
+        );
+    }
+    let mut distinct = outputs.clone();
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(distinct.len(), outputs.len(), "{:?}", outputs);
+}
+
+#[tokio::test]
+async fn format_accepts_plain_text() {
+    let template = "{% if x %}\n{{ \"a\\b\" }}\n{% endif %}";
+    let json = serde_json::json!({ "input": template }).to_string();
+    let from_json = post("/format", &json).await;
+    assert_eq!(from_json.0, StatusCode::OK);
+
+    let request = Request::post("/format")
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(template))
+        .unwrap();
+    let response = server::router().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        (status, String::from_utf8(body.to_vec()).unwrap()),
+        from_json
+    );
+}
+
+#[tokio::test]
+async fn format_empty_input() {
+    assert_eq!(
+        post("/format", r#"{"input": ""}"#).await,
+        (StatusCode::OK, String::new())
+    );
+}
+
+#[tokio::test]
+async fn format_check_reports_would_change() {
+    assert_eq!(
+        post(
+            "/format",
+            r#"{"input": "{% if x %}{{ x }}{% endif %}", "check": true}"#
+        )
+        .await,
+        (StatusCode::OK, r#"{"would_change":true}"#.to_string())
+    );
+    assert_eq!(
+        post("/format", r#"{"input": "{{ x }}\n", "check": true}"#).await,
+        (StatusCode::OK, r#"{"would_change":false}"#.to_string())
+    );
+}
+
+#[tokio::test]
+async fn format_reports_changes_in_headers() {
+    let changes = |input: &str| {
+        let request = Request::post("/format")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                serde_json::json!({ "input": input }).to_string(),
+            ))
+            .unwrap();
+        async {
+            let response = server::router().oneshot(request).await.unwrap();
+            let header = |name| response.headers()[name].to_str().unwrap().to_string();
+            (header("X-Jinja-Changed"), header("X-Jinja-Lines-Changed"))
+        }
+    };
+    assert_eq!(
+        changes("{% if x %}\n  {{ x }}\n{% endif %}\n").await,
+        ("false".to_string(), "0".to_string())
+    );
+    assert_eq!(
+        changes("{% if x %}{{ x }}{% endif %}").await,
+        ("true".to_string(), "3".to_string())
+    );
+}
+
+#[tokio::test]
+async fn format_batch_preserves_order() {
+    let body = r#"[
+        {"id": "a", "input": "{% if x %}{{ x }}{% endif %}"},
+        {"id": 2, "input": "{% endif %}"},
+        {"id": "c", "input": "{% for x in xs %}{{ x }}{% endfor %}", "indent_size": 4}
+    ]"#;
+    let (status, body) = post("/format/batch", body).await;
+    assert_eq!(status, StatusCode::OK);
+    let results: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        results,
+        serde_json::json!([
+            {"id": "a", "output": "{% if x %}\n  {{ x }}\n{% endif %}\n"},
+            {"id": 2, "error": "Unbalanced block: unexpected `endif` at line 1, column 1"},
+            {"id": "c", "output": "{% for x in xs %}\n    {{ x }}\n{% endfor %}\n"},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn large_batch_keeps_order() {
+    let items: Vec<_> = (0..500)
+        .map(|i| match i % 7 {
+            0 => serde_json::json!({ "id": i, "input": "{% endif %}" }),
+            _ => serde_json::json!({ "id": i, "input": format!("{{% if x %}}{{{{x{i}}}}}{{% endif %}}") }),
+        })
+        .collect();
+    let (status, body) = post("/format/batch", &serde_json::Value::from(items).to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    let results: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+    assert_eq!(results.len(), 500);
+    for (i, result) in results.iter().enumerate() {
+        assert_eq!(result["id"], i);
+        if i % 7 == 0 {
+            assert!(result["error"].is_string(), "{result}");
+        } else {
+            assert_eq!(
+                result["output"],
+                format!("{{% if x %}}\n  {{{{ x{i} }}}}\n{{% endif %}}\n")
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn format_json_response_shape() {
+    let (status, body) = post(
+        "/format",
+        r#"{"input": "{% for x in xs %}{% if x %}{{ x }}{% endif %}{% endfor %}", "format": "json"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "output": "{% for x in xs %}\n  {% if x %}\n    {{ x }}\n  {% endif %}\n{% endfor %}\n",
+            "changed": true,
+            "line_count": 5,
+            "block_depth_max": 2,
+        })
+    );
+
+    let request = Request::post("/format")
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/json")
+        .body(Body::from(r#"{"input": "{{ x }}\n"}"#))
+        .unwrap();
+    let response = server::router().oneshot(request).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["changed"], false);
+}
+
+#[tokio::test]
+async fn format_diff_output() {
+    let (status, body) = post(
+        "/format",
+        r#"{"input": "{% if x %}\n{{ x }}\n{% endif %}\n", "output": "diff"}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        "--- input\n+++ input (formatted)\n@@ -1,3 +1,3 @@\n {% if x %}\n-{{ x }}\n+  {{ x }}\n {% endif %}\n"
+    );
+
+    let (status, body) = post("/format", r#"{"input": "{{ x }}\n", "output": "diff"}"#).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "");
+}